@@ -11,7 +11,8 @@ use camera_controllers::{CameraPerspective, OrbitZoomCamera, OrbitZoomCameraSett
 use cgmath::Matrix4;
 use gfx::Factory;
 use gfx::traits::FactoryExt;
-use gfx_draping::{DrapingRenderer, Polygon, PolygonBuffer, PolygonBufferIndices};
+use gfx_draping::{BlendMode, DepthConvention, DrapingRenderer, Polygon, PolygonBuffer,
+                  PolygonBufferIndices, RenderParams};
 use piston_window::{OpenGL, PistonWindow, RenderEvent, ResizeEvent, Window, WindowSettings};
 
 gfx_vertex_struct!(Vertex {
@@ -159,7 +160,7 @@ fn main() {
     }
 
     // Finally, prepare the polygons for rendering.
-    let renderer = DrapingRenderer::new(&mut factory);
+    let renderer = DrapingRenderer::new(&mut factory, DepthConvention::Standard);
     let renderable_buffer = buffer.as_renderable(&mut factory);
     let renderable_indices1 = indices1.as_renderable(&mut factory);
     let renderable_indices2 = indices2.as_renderable(&mut factory);
@@ -196,25 +197,32 @@ fn main() {
             terrain_bundle.encode(&mut window.encoder);
 
             let cgmath_mvp: Matrix4<f32> = mvp.into();
+            let draping_mvp = (cgmath_mvp * polygon_model).into();
 
             renderer.render(
                 &mut window.encoder,
                 window.output_color.clone(),
                 window.output_stencil.clone(),
-                (cgmath_mvp * polygon_model).into(),
-                [0.0, 0.0, 1.0, 0.5],
-                &renderable_buffer,
-                &renderable_indices1,
+                BlendMode::Alpha,
+                RenderParams::new(
+                    draping_mvp,
+                    [0.0, 0.0, 1.0, 0.5],
+                    &renderable_buffer,
+                    &renderable_indices1.cull(draping_mvp),
+                ),
             );
 
             renderer.render(
                 &mut window.encoder,
                 window.output_color.clone(),
                 window.output_stencil.clone(),
-                (cgmath_mvp * polygon_model).into(),
-                [0.0, 1.0, 1.0, 0.5],
-                &renderable_buffer,
-                &renderable_indices2,
+                BlendMode::Alpha,
+                RenderParams::new(
+                    draping_mvp,
+                    [0.0, 1.0, 1.0, 0.5],
+                    &renderable_buffer,
+                    &renderable_indices2.cull(draping_mvp),
+                ),
             );
         });
 
@@ -22,7 +22,8 @@ use geojson::GeoJson;
 use geojson::conversion::TryInto;
 use gfx::Factory;
 use gfx::traits::FactoryExt;
-use gfx_draping::{DrapingRenderer, PolygonBuffer, PolygonBufferIndices};
+use gfx_draping::{BlendMode, DepthConvention, DrapingRenderer, PolygonBuffer,
+                  PolygonBufferIndices, RenderParams};
 use piston_window::{OpenGL, PistonWindow, RenderEvent, ResizeEvent, Window, WindowSettings};
 
 gfx_vertex_struct!(Vertex {
@@ -171,7 +172,7 @@ fn main() {
         data: terrain_data,
     };
 
-    let renderer = DrapingRenderer::new(&mut factory);
+    let renderer = DrapingRenderer::new(&mut factory, DepthConvention::Standard);
     let mut buffer = PolygonBuffer::new();
     let mut indices = PolygonBufferIndices::new();
     for polygon in multi_polygon {
@@ -220,10 +221,13 @@ fn main() {
                 &mut window.encoder,
                 window.output_color.clone(),
                 window.output_stencil.clone(),
-                (cgmath_mvp * polygon_model).into(),
-                [0.0, 0.0, 1.0, 0.5],
-                &renderable_buffer,
-                &renderable_indices,
+                BlendMode::Alpha,
+                RenderParams::new(
+                    (cgmath_mvp * polygon_model).into(),
+                    [0.0, 0.0, 1.0, 0.5],
+                    &renderable_buffer,
+                    &renderable_indices,
+                ),
             );
 
             let fps_message = format!("Frames per second: {}", fps_counter.tick());
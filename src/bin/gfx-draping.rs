@@ -0,0 +1,794 @@
+//! Prepare polygon data for `gfx_draping` ahead of time.
+//!
+//! Run `gfx-draping help` for usage.
+
+extern crate geo;
+extern crate geojson;
+extern crate gfx_draping;
+extern crate shapefile;
+extern crate wkt;
+
+use std::env;
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{self, Command};
+
+use geo::{LineString, Point};
+use geo::simplify::Simplify;
+use gfx_draping::{shader_programs, BufferFile, CapTriangulation, Ellipsoid, Globe, Polygon,
+                  PolygonBuffer, PolygonBufferIndices, Surface, UpAxis};
+
+const USAGE: &str = "\
+Prepare polygon data for gfx_draping ahead of time.
+
+USAGE:
+    gfx-draping build [OPTIONS] --output <FILE> <INPUT>...
+    gfx-draping inspect <FILE>...
+    gfx-draping check-shaders [--output <DIR>]
+
+The `build` command reads polygons from each INPUT, and writes them to a single buffer file. Each
+input becomes an index set named after the input's file name, without its extension. Inputs may be
+GeoJSON (.geojson, .json), Shapefiles (.shp) or WKT (.wkt, one geometry per line).
+
+Rings are closed and re-oriented as gfx_draping expects, and rings that can't be drawn are dropped
+with a warning.
+
+BUILD OPTIONS:
+    --output <FILE>              Where to write the buffer file.
+    --projection <PROJECTION>    How to project input coordinates, one of:
+                                   none:     use coordinates as-is (the default)
+                                   mercator: Web Mercator, in meters
+                                   globe:    longitude and latitude on the WGS84 ellipsoid
+    --up-axis <AXIS>             The up-axis of planar outputs: x, y or z (the default).
+    --min-height <METERS>        With `--projection globe`, the lowest terrain height.
+    --max-height <METERS>        With `--projection globe`, the highest terrain height.
+    --max-edge-length <DEGREES>  With `--projection globe`, the longest edge to keep unsplit
+                                 (default: 1).
+    --simplify <TOLERANCE>       Simplify rings, in input units, before projecting them.
+    --triangulation <METHOD>     How to triangulate the caps of shadow volumes, one of:
+                                   fan:          quickest to build (the default)
+                                   ear-clipping: cheaper to draw for concave polygons
+    --strict                     Fail instead of warning when input needs to be fixed.
+
+The `inspect` command prints a summary of each buffer file: vertex and triangle counts, per-index-set
+polygon counts, bounding extents and validation warnings.
+
+The `check-shaders` command compiles and links every shader variant the renderer can build, for
+GLSL 1.50 and GLSL ES 3.00, without a GPU, using glslangValidator, which must be on the PATH. Each
+variant is written as `<NAME>.vert` and `<NAME>.frag` to DIR if `--output` is given, and to a
+temporary directory otherwise.
+";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(|command| command.as_str()) {
+        Some("build") => build(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("check-shaders") => check_shaders(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("expected a command\n\n{}", USAGE)),
+    };
+
+    if let Err(message) = result {
+        eprintln!("error: {}", message);
+        process::exit(1);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Projection {
+    None,
+    Mercator,
+    Globe,
+}
+
+struct BuildOptions {
+    output: Option<String>,
+    inputs: Vec<String>,
+    projection: Projection,
+    up_axis: UpAxis,
+    min_height: Option<f32>,
+    max_height: Option<f32>,
+    max_edge_length: f32,
+    simplify: Option<f32>,
+    triangulation: CapTriangulation,
+    strict: bool,
+}
+
+impl BuildOptions {
+    fn parse(args: &[String]) -> Result<BuildOptions, String> {
+        let mut options = BuildOptions {
+            output: None,
+            inputs: Vec::new(),
+            projection: Projection::None,
+            up_axis: UpAxis::Z,
+            min_height: None,
+            max_height: None,
+            max_edge_length: 1.0,
+            simplify: None,
+            triangulation: CapTriangulation::Fan,
+            strict: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next().ok_or_else(
+                    || format!("missing value for {}", arg),
+                )
+            };
+
+            match arg.as_str() {
+                "--output" => options.output = Some(value()?.clone()),
+                "--projection" => {
+                    options.projection = match value()?.as_str() {
+                        "none" => Projection::None,
+                        "mercator" => Projection::Mercator,
+                        "globe" => Projection::Globe,
+                        other => return Err(format!("unknown projection: {}", other)),
+                    }
+                }
+                "--up-axis" => {
+                    options.up_axis = match value()?.as_str() {
+                        "x" => UpAxis::X,
+                        "y" => UpAxis::Y,
+                        "z" => UpAxis::Z,
+                        other => return Err(format!("unknown up-axis: {}", other)),
+                    }
+                }
+                "--min-height" => options.min_height = Some(parse_number(arg, value()?)?),
+                "--max-height" => options.max_height = Some(parse_number(arg, value()?)?),
+                "--max-edge-length" => options.max_edge_length = parse_number(arg, value()?)?,
+                "--simplify" => options.simplify = Some(parse_number(arg, value()?)?),
+                "--triangulation" => {
+                    options.triangulation = match value()?.as_str() {
+                        "fan" => CapTriangulation::Fan,
+                        "ear-clipping" => CapTriangulation::EarClipping,
+                        other => return Err(format!("unknown triangulation: {}", other)),
+                    }
+                }
+                "--strict" => options.strict = true,
+                flag if flag.starts_with("--") => return Err(format!("unknown option: {}", flag)),
+                input => options.inputs.push(input.to_owned()),
+            }
+        }
+
+        if options.output.is_none() {
+            return Err("missing --output".to_owned());
+        }
+
+        if options.inputs.is_empty() {
+            return Err("no inputs given".to_owned());
+        }
+
+        Ok(options)
+    }
+
+    fn surface(&self) -> Result<Surface, String> {
+        if self.projection != Projection::Globe {
+            return Ok(Surface::Plane(self.up_axis));
+        }
+
+        match (self.min_height, self.max_height) {
+            (Some(min_height), Some(max_height)) => Ok(Surface::Globe(Globe::new(
+                Ellipsoid::wgs84(),
+                min_height,
+                max_height,
+                self.max_edge_length,
+            ))),
+            _ => Err(
+                "--projection globe requires --min-height and --max-height".to_owned(),
+            ),
+        }
+    }
+
+    fn project(&self, (x, y): (f64, f64)) -> (f32, f32) {
+        match self.projection {
+            Projection::None | Projection::Globe => (x as f32, y as f32),
+            Projection::Mercator => {
+                // Web Mercator is undefined at the poles, so clamp to its usual extent.
+                let radius = 6_378_137.0;
+                let latitude = y.clamp(-85.051_13, 85.051_13) * PI / 180.0;
+                (
+                    (radius * x * PI / 180.0) as f32,
+                    (radius * (PI / 4.0 + latitude / 2.0).tan().ln()) as f32,
+                )
+            }
+        }
+    }
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<f32, String> {
+    value.parse().map_err(
+        |_| format!("invalid number for {}: {}", flag, value),
+    )
+}
+
+/// The problems found, and fixed, while reading an input.
+#[derive(Default)]
+struct Warnings {
+    unclosed_rings: usize,
+    reoriented_rings: usize,
+    degenerate_rings: usize,
+    non_finite_rings: usize,
+    orphaned_holes: usize,
+    short_positions: usize,
+    skipped_geometries: usize,
+}
+
+impl Warnings {
+    fn messages(&self) -> Vec<String> {
+        let counts = [
+            (self.unclosed_rings, "rings were not closed, and have been closed"),
+            (self.reoriented_rings, "rings had the wrong orientation, and have been reversed"),
+            (self.degenerate_rings, "rings had fewer than three distinct points, and were dropped"),
+            (self.non_finite_rings, "rings had non-finite coordinates, and were dropped"),
+            (self.orphaned_holes, "holes had no exterior ring, and were dropped"),
+            (
+                self.short_positions,
+                "polygons had positions with fewer than two coordinates, and were dropped",
+            ),
+            (self.skipped_geometries, "geometries were not polygons, and were skipped"),
+        ];
+
+        counts
+            .iter()
+            .filter(|&&(count, _)| count > 0)
+            .map(|&(count, message)| format!("{} {}", count, message))
+            .collect()
+    }
+}
+
+/// A polygon as read from an input: an exterior ring followed by its holes.
+type Rings = Vec<Vec<(f64, f64)>>;
+
+fn build(args: &[String]) -> Result<(), String> {
+    let options = BuildOptions::parse(args)?;
+    let mut buffer = PolygonBuffer::with_surface(options.surface()?);
+    buffer.set_cap_triangulation(options.triangulation);
+    let mut index_sets = Vec::new();
+    let mut failed = false;
+
+    for input in &options.inputs {
+        let mut warnings = Warnings::default();
+        let polygons = read_input(input, &mut warnings)?;
+
+        let mut indices = PolygonBufferIndices::new();
+        for rings in polygons {
+            if let Some(polygon) = prepare_polygon(&options, rings, &mut warnings) {
+                indices.extend(&buffer.add(&polygon));
+            }
+        }
+
+        for message in warnings.messages() {
+            eprintln!("warning: {}: {}", input, message);
+            failed = options.strict;
+        }
+
+        let name = Path::new(input)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| input.clone());
+        index_sets.push((name, indices));
+    }
+
+    if failed {
+        return Err("input needed fixing, and --strict was given".to_owned());
+    }
+
+    let output = options.output.as_ref().unwrap();
+    let file = File::create(output).map_err(|error| format!("{}: {}", output, error))?;
+    let index_sets: Vec<_> = index_sets
+        .iter()
+        .map(|(name, indices)| (name.as_str(), indices))
+        .collect();
+
+    BufferFile::write(&mut BufWriter::new(file), &buffer, &index_sets)
+        .map_err(|error| format!("{}: {}", output, error))
+}
+
+/// Clean up, simplify and project one polygon's rings.
+///
+/// Returns `None` if nothing drawable is left of the polygon.
+fn prepare_polygon(options: &BuildOptions, rings: Rings, warnings: &mut Warnings) -> Option<Polygon> {
+    let mut points = Vec::new();
+    let mut bounds = [(std::f32::MAX, std::f32::MIN), (std::f32::MAX, std::f32::MIN)];
+    let num_holes = rings.len().saturating_sub(1);
+
+    for (index, ring) in rings.into_iter().enumerate() {
+        let is_exterior = index == 0;
+        let ring = match clean_ring(ring, is_exterior, options.simplify, warnings) {
+            Some(ring) => ring,
+            None if is_exterior => {
+                warnings.orphaned_holes += num_holes;
+                return None;
+            }
+            None => continue,
+        };
+
+        for point in ring {
+            let (x, y) = options.project(point);
+            bounds[0] = (bounds[0].0.min(x), bounds[0].1.max(x));
+            bounds[1] = (bounds[1].0.min(y), bounds[1].1.max(y));
+            points.push((x, y));
+        }
+    }
+
+    Some(Polygon::new(bounds, points))
+}
+
+/// Make `ring` closed and correctly oriented, or drop it if it can't be drawn.
+fn clean_ring(
+    mut ring: Vec<(f64, f64)>,
+    is_exterior: bool,
+    simplify: Option<f32>,
+    warnings: &mut Warnings,
+) -> Option<Vec<(f64, f64)>> {
+    if ring.iter().any(|&(x, y)| !x.is_finite() || !y.is_finite()) {
+        warnings.non_finite_rings += 1;
+        return None;
+    }
+
+    ring.dedup();
+    if ring.len() > 1 && ring.first() != ring.last() {
+        warnings.unclosed_rings += 1;
+        let first = ring[0];
+        ring.push(first);
+    }
+
+    if let Some(epsilon) = simplify {
+        let line = LineString(ring.iter().map(|&(x, y)| Point::new(x, y)).collect());
+        ring = line.simplify(&(epsilon as f64))
+            .0
+            .into_iter()
+            .map(|point| (point.x(), point.y()))
+            .collect();
+    }
+
+    // A closed ring needs at least three distinct points, plus the repeated first one.
+    if ring.len() < 4 {
+        warnings.degenerate_rings += 1;
+        return None;
+    }
+
+    // Exterior rings must be counter-clockwise, and holes clockwise.
+    if (signed_area(&ring) > 0.0) != is_exterior {
+        warnings.reoriented_rings += 1;
+        ring.reverse();
+    }
+
+    Some(ring)
+}
+
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    ring.windows(2)
+        .map(|edge| edge[0].0 * edge[1].1 - edge[1].0 * edge[0].1)
+        .sum::<f64>() / 2.0
+}
+
+fn read_input(path: &str, warnings: &mut Warnings) -> Result<Vec<Rings>, String> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let result = match extension.as_str() {
+        "geojson" | "json" => read_geojson(path, warnings),
+        "shp" => read_shapefile(path, warnings),
+        "wkt" => read_wkt(path, warnings),
+        _ => Err("unrecognized file extension".to_owned()),
+    };
+
+    result.map_err(|error| format!("{}: {}", path, error))
+}
+
+fn read_to_string(path: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|error: io::Error| error.to_string())?;
+
+    Ok(contents)
+}
+
+fn read_geojson(path: &str, warnings: &mut Warnings) -> Result<Vec<Rings>, String> {
+    use geojson::{GeoJson, Geometry, Value};
+
+    fn add_polygon(rings: Vec<Vec<Vec<f64>>>, polygons: &mut Vec<Rings>, warnings: &mut Warnings) {
+        let is_short = |position: &Vec<f64>| position.len() < 2;
+        if rings.iter().any(|ring| ring.iter().any(is_short)) {
+            warnings.short_positions += 1;
+            return;
+        }
+
+        polygons.push(
+            rings
+                .into_iter()
+                .map(|ring| {
+                    ring.into_iter()
+                        .map(|position| (position[0], position[1]))
+                        .collect()
+                })
+                .collect(),
+        );
+    }
+
+    fn add_geometry(geometry: Geometry, polygons: &mut Vec<Rings>, warnings: &mut Warnings) {
+        match geometry.value {
+            Value::Polygon(rings) => add_polygon(rings, polygons, warnings),
+            Value::MultiPolygon(multi_polygon) => {
+                for rings in multi_polygon {
+                    add_polygon(rings, polygons, warnings);
+                }
+            }
+            Value::GeometryCollection(geometries) => {
+                for geometry in geometries {
+                    add_geometry(geometry, polygons, warnings);
+                }
+            }
+            _ => warnings.skipped_geometries += 1,
+        }
+    }
+
+    let geojson: GeoJson = read_to_string(path)?.parse().map_err(
+        |error: geojson::Error| error.to_string(),
+    )?;
+
+    let geometries = match geojson {
+        GeoJson::FeatureCollection(collection) => {
+            collection
+                .features
+                .into_iter()
+                .filter_map(|feature| feature.geometry)
+                .collect()
+        }
+        GeoJson::Feature(feature) => feature.geometry.into_iter().collect(),
+        GeoJson::Geometry(geometry) => vec![geometry],
+    };
+
+    let mut polygons = Vec::new();
+    for geometry in geometries {
+        add_geometry(geometry, &mut polygons, warnings);
+    }
+
+    Ok(polygons)
+}
+
+fn read_shapefile(path: &str, warnings: &mut Warnings) -> Result<Vec<Rings>, String> {
+    let shapes = shapefile::read(path).map_err(
+        |error| format!("{:?}", error),
+    )?;
+
+    let mut polygons: Vec<Rings> = Vec::new();
+    for shape in shapes {
+        let (points, parts): (Vec<(f64, f64)>, Vec<i32>) = match shape {
+            shapefile::Shape::Polygon(polygon) => {
+                (polygon.points.iter().map(|p| (p.x, p.y)).collect(), polygon.parts)
+            }
+            shapefile::Shape::PolygonM(polygon) => {
+                (polygon.points.iter().map(|p| (p.x, p.y)).collect(), polygon.parts)
+            }
+            shapefile::Shape::PolygonZ(polygon) => {
+                (polygon.points.iter().map(|p| (p.x, p.y)).collect(), polygon.parts)
+            }
+            _ => {
+                warnings.skipped_geometries += 1;
+                continue;
+            }
+        };
+
+        polygons.extend(group_shapefile_rings(&points, &parts));
+    }
+
+    Ok(polygons)
+}
+
+/// Split a shapefile shape's `points` into polygons, at the ring starts given by `parts`.
+///
+/// Shapefiles store every ring of a multi-polygon in one list. Exterior rings are clockwise, and
+/// each is followed by its holes, which are counter-clockwise.
+fn group_shapefile_rings(points: &[(f64, f64)], parts: &[i32]) -> Vec<Rings> {
+    let mut polygons: Vec<Rings> = Vec::new();
+    let mut starts: Vec<usize> = parts
+        .iter()
+        .map(|&part| (part.max(0) as usize).min(points.len()))
+        .collect();
+    starts.push(points.len());
+
+    for part in starts.windows(2) {
+        let ring = points[part[0]..part[1].max(part[0])].to_vec();
+        if signed_area(&ring) <= 0.0 || polygons.is_empty() {
+            polygons.push(vec![ring]);
+        } else {
+            polygons.last_mut().unwrap().push(ring);
+        }
+    }
+
+    polygons
+}
+
+fn read_wkt(path: &str, warnings: &mut Warnings) -> Result<Vec<Rings>, String> {
+    use wkt::{Geometry, Wkt};
+
+    fn polygon(polygon: wkt::types::Polygon) -> Rings {
+        polygon
+            .0
+            .into_iter()
+            .map(|ring| ring.0.into_iter().map(|coord| (coord.x, coord.y)).collect())
+            .collect()
+    }
+
+    fn add_geometry(geometry: Geometry, polygons: &mut Vec<Rings>, warnings: &mut Warnings) {
+        match geometry {
+            Geometry::Polygon(p) => polygons.push(polygon(p)),
+            Geometry::MultiPolygon(multi_polygon) => {
+                polygons.extend(multi_polygon.0.into_iter().map(polygon))
+            }
+            Geometry::GeometryCollection(collection) => {
+                for geometry in collection.0 {
+                    add_geometry(geometry, polygons, warnings);
+                }
+            }
+            _ => warnings.skipped_geometries += 1,
+        }
+    }
+
+    let mut polygons = Vec::new();
+    for (number, line) in read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let wkt = Wkt::from_str(line).map_err(
+            |error| format!("line {}: {}", number + 1, error),
+        )?;
+
+        for geometry in wkt.items {
+            add_geometry(geometry, &mut polygons, warnings);
+        }
+    }
+
+    Ok(polygons)
+}
+
+fn inspect(paths: &[String]) -> Result<(), String> {
+    if paths.is_empty() {
+        return Err("no files given".to_owned());
+    }
+
+    for path in paths {
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|error| format!("{}: {}", path, error))?;
+
+        let file = BufferFile::parse(&bytes).map_err(
+            |error| format!("{}: {}", path, error),
+        )?;
+
+        let (polyhedron_vertices, bounding_box_vertices) = file.vertex_counts();
+        println!("{}:", path);
+        println!("  surface: {:?}", file.surface());
+        println!(
+            "  vertices: {} polyhedron, {} bounding box",
+            polyhedron_vertices,
+            bounding_box_vertices
+        );
+
+        let mut warnings = Vec::new();
+        for name in file.index_set_names() {
+            let indices = file.to_indices(name).unwrap();
+            let (polyhedron_triangles, bounding_box_triangles) = indices.triangle_counts();
+
+            println!("  index set {:?}:", name);
+            println!("    polygons: {}", indices.len());
+            println!(
+                "    triangles: {} polyhedron, {} bounding box",
+                polyhedron_triangles,
+                bounding_box_triangles
+            );
+
+            if indices.is_empty() {
+                warnings.push(format!("index set {:?} is empty", name));
+                continue;
+            }
+
+            let mut extent = [[std::f32::MAX; 3], [std::f32::MIN; 3]];
+            for (index, bounds) in indices.polygon_bounds().enumerate() {
+                let corners = bounds[0].iter().chain(&bounds[1]);
+                if corners.clone().any(|coordinate| !coordinate.is_finite()) {
+                    warnings.push(format!(
+                        "polygon {} of index set {:?} has non-finite coordinates",
+                        index,
+                        name
+                    ));
+                    continue;
+                }
+
+                if (0..3).filter(|&axis| bounds[0][axis] == bounds[1][axis]).count() > 1 {
+                    warnings.push(format!(
+                        "polygon {} of index set {:?} has zero area",
+                        index,
+                        name
+                    ));
+                }
+
+                for axis in 0..3 {
+                    extent[0][axis] = extent[0][axis].min(bounds[0][axis]);
+                    extent[1][axis] = extent[1][axis].max(bounds[1][axis]);
+                }
+            }
+
+            println!("    extent: {:?} to {:?}", extent[0], extent[1]);
+        }
+
+        for warning in warnings {
+            println!("  warning: {}", warning);
+        }
+    }
+
+    Ok(())
+}
+
+fn check_shaders(args: &[String]) -> Result<(), String> {
+    let dir = match args {
+        [] => env::temp_dir().join(format!("gfx-draping-shaders-{}", process::id())),
+        [flag, dir] if flag == "--output" => Path::new(dir).to_owned(),
+        _ => return Err(format!("unexpected arguments: {}", args.join(" "))),
+    };
+
+    fs::create_dir_all(&dir).map_err(|error| format!("{}: {}", dir.display(), error))?;
+
+    let mut failures = 0;
+    for program in shader_programs() {
+        let mut paths = Vec::new();
+        for &(extension, source) in &[("vert", &program.vertex), ("frag", &program.fragment)] {
+            let path = dir.join(format!("{}.{}", program.name, extension));
+            File::create(&path)
+                .and_then(|mut file| file.write_all(source.as_bytes()))
+                .map_err(|error| format!("{}: {}", path.display(), error))?;
+            paths.push(path);
+        }
+
+        let output = Command::new("glslangValidator")
+            .arg("-l")
+            .args(&paths)
+            .output()
+            .map_err(|error| format!("could not run glslangValidator: {}", error))?;
+
+        if output.status.success() {
+            println!("ok: {}", program.name);
+        } else {
+            println!("FAILED: {}", program.name);
+            print!("{}", String::from_utf8_lossy(&output.stdout));
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        return Err(format!("{} shader programs failed to compile", failures));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    fn square(min: f64, size: f64, clockwise: bool) -> Vec<(f64, f64)> {
+        let max = min + size;
+        let mut ring = vec![(min, min), (max, min), (max, max), (min, max), (min, min)];
+        if clockwise {
+            ring.reverse();
+        }
+        ring
+    }
+
+    #[test]
+    fn build_options_are_parsed() {
+        let options = BuildOptions::parse(&args(&[
+            "--output",
+            "out.bin",
+            "--projection",
+            "globe",
+            "--min-height",
+            "-10",
+            "--max-height",
+            "8848",
+            "--triangulation",
+            "ear-clipping",
+            "--strict",
+            "a.geojson",
+            "b.wkt",
+        ])).unwrap();
+
+        assert_eq!(options.output, Some("out.bin".to_owned()));
+        assert_eq!(options.inputs, args(&["a.geojson", "b.wkt"]));
+        assert_eq!(options.projection, Projection::Globe);
+        assert_eq!(options.min_height, Some(-10.0));
+        assert_eq!(options.max_height, Some(8848.0));
+        assert_eq!(options.max_edge_length, 1.0);
+        assert_eq!(options.triangulation, CapTriangulation::EarClipping);
+        assert!(options.strict);
+        assert!(options.surface().is_ok());
+    }
+
+    #[test]
+    fn invalid_build_options_are_errors() {
+        let invalid: &[&[&str]] = &[
+            &["a.geojson"],
+            &["--output", "out.bin"],
+            &["--output"],
+            &["--output", "out.bin", "--frobnicate", "a.geojson"],
+            &["--output", "out.bin", "--projection", "lambert", "a.geojson"],
+            &["--output", "out.bin", "--simplify", "lots", "a.geojson"],
+            &["--output", "out.bin", "--triangulation", "strips", "a.geojson"],
+        ];
+
+        for &arguments in invalid {
+            assert!(BuildOptions::parse(&args(arguments)).is_err(), "{:?}", arguments);
+        }
+
+        let globe = args(&["--output", "out.bin", "--projection", "globe", "a.geojson"]);
+        assert!(BuildOptions::parse(&globe).unwrap().surface().is_err());
+    }
+
+    #[test]
+    fn shapefile_rings_are_grouped_into_polygons() {
+        let rings = [
+            square(0.0, 10.0, true),
+            square(1.0, 2.0, false),
+            square(5.0, 2.0, false),
+            square(20.0, 10.0, true),
+            square(21.0, 2.0, false),
+        ];
+        let mut points = Vec::new();
+        let mut parts = Vec::new();
+        for ring in &rings {
+            parts.push(points.len() as i32);
+            points.extend_from_slice(ring);
+        }
+
+        let polygons = group_shapefile_rings(&points, &parts);
+        assert_eq!(polygons, vec![rings[..3].to_vec(), rings[3..].to_vec()]);
+    }
+
+    #[test]
+    fn shapefiles_starting_with_a_hole_still_make_a_polygon() {
+        let rings = [square(1.0, 2.0, false), square(5.0, 2.0, false)];
+        let points: Vec<_> = rings.iter().flat_map(|ring| ring.clone()).collect();
+
+        let polygons = group_shapefile_rings(&points, &[0, 5]);
+        assert_eq!(polygons, vec![rings.to_vec()]);
+    }
+
+    #[test]
+    fn geojson_positions_without_two_coordinates_are_dropped_with_a_warning() {
+        let path = env::temp_dir().join(format!("gfx-draping-{}.geojson", process::id()));
+        fs::write(
+            &path,
+            r#"{"type": "MultiPolygon", "coordinates": [
+                [[[0, 0], [1, 0], [1, 1], [0, 0]]],
+                [[[0, 0], [1], [1, 1], [0, 0]]]
+            ]}"#,
+        ).unwrap();
+
+        let mut warnings = Warnings::default();
+        let polygons = read_geojson(path.to_str().unwrap(), &mut warnings);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(polygons.unwrap().len(), 1);
+        assert_eq!(warnings.short_positions, 1);
+    }
+}
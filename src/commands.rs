@@ -0,0 +1,332 @@
+use std::ops::Range;
+
+use render::DepthConvention;
+
+/// One of the two passes of the z-fail algorithm, which every draped polygon is drawn with.
+///
+/// Every polyhedron must be drawn before any bounding box, because drawing a bounding box resets
+/// the stencil buffer wherever it is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Draw the polyhedra, counting in the stencil buffer how many of their faces lie behind the
+    /// terrain. This leaves the stencil buffer nonzero wherever a polyhedron encloses the terrain,
+    /// and doesn't change the render target.
+    Polyhedron,
+    /// Draw the bounding boxes wherever the stencil buffer is nonzero, resetting it to zero. This
+    /// is the pass that colors the terrain.
+    Cover,
+}
+
+impl Pass {
+    /// The geometry drawn in this pass.
+    pub fn geometry(&self) -> Geometry {
+        match *self {
+            Pass::Polyhedron => Geometry::Polyhedra,
+            Pass::Cover => Geometry::BoundingBoxes,
+        }
+    }
+
+    /// The stencil state of this pass, which is the same whatever `DepthConvention` the terrain
+    /// uses.
+    pub fn stencil(&self) -> StencilState {
+        match *self {
+            // These count the faces that fail the depth test, i.e. lie behind the terrain.
+            Pass::Polyhedron => StencilState {
+                front: StencilFace {
+                    test: Comparison::Always,
+                    op_fail: StencilOp::Keep,
+                    op_depth_fail: StencilOp::DecrementWrap,
+                    op_pass: StencilOp::Keep,
+                },
+                back: StencilFace {
+                    test: Comparison::Always,
+                    op_fail: StencilOp::Keep,
+                    op_depth_fail: StencilOp::IncrementWrap,
+                    op_pass: StencilOp::Keep,
+                },
+                reference: 0,
+            },
+            Pass::Cover => {
+                // A fragment is only "inside" the polyhedron, and thus supposed to be drawn, if
+                // the stencil buffer is nonzero at that point. Where it is drawn, the stencil is
+                // reset to zero, so that the next draw doesn't need to clear the stencil first.
+                // The depth test always passes, so `op_depth_fail` never happens.
+                let face = StencilFace {
+                    test: Comparison::NotEqual,
+                    op_fail: StencilOp::Keep,
+                    op_depth_fail: StencilOp::Keep,
+                    op_pass: StencilOp::Replace,
+                };
+
+                StencilState {
+                    front: face,
+                    back: face,
+                    reference: 0,
+                }
+            }
+        }
+    }
+
+    /// The full pipeline state of this pass, for terrain drawn with the `depth` convention.
+    pub fn state(&self, depth: DepthConvention) -> DrawState {
+        match *self {
+            Pass::Polyhedron => DrawState {
+                depth_test: match depth {
+                    DepthConvention::Standard => Comparison::LessEqual,
+                    DepthConvention::Reversed => Comparison::GreaterEqual,
+                },
+                cull_face: CullFace::Nothing,
+                writes_color: false,
+                stencil: self.stencil(),
+            },
+            // Drawing only the back faces, without a depth test, covers the polygon even when
+            // the camera is inside its bounding box.
+            Pass::Cover => DrawState {
+                depth_test: Comparison::Always,
+                cull_face: CullFace::Front,
+                writes_color: true,
+                stencil: self.stencil(),
+            },
+        }
+    }
+}
+
+/// Which of a `PolygonBuffer`'s two vertex buffers, and the matching indices, a draw reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Geometry {
+    /// The polyhedra, one per polygon, which extrude the polygon through the terrain.
+    Polyhedra,
+    /// The bounding boxes, one per polygon, which enclose its polyhedron. Despite the name, each
+    /// is the polygon's `CoverVolume`, which may be tighter than a box.
+    BoundingBoxes,
+}
+
+/// The pipeline state a `Pass` is drawn with, independent of any graphics API.
+///
+/// Depth writes are always off, and stencil tests read and write every bit of the stencil buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawState {
+    /// How fragments' depths are compared to the terrain's.
+    pub depth_test: Comparison,
+    /// Which faces are culled.
+    pub cull_face: CullFace,
+    /// Whether the render target is written to, rather than only the stencil buffer.
+    pub writes_color: bool,
+    /// The stencil test and operations.
+    pub stencil: StencilState,
+}
+
+/// The stencil test and operations of a `Pass`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilState {
+    /// The test and operations for front faces, which are wound counter-clockwise.
+    pub front: StencilFace,
+    /// The test and operations for back faces.
+    pub back: StencilFace,
+    /// The value the stencil buffer is compared to, and that `StencilOp::Replace` writes.
+    pub reference: u8,
+}
+
+/// The stencil test and operations for one side of a face.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilFace {
+    /// How the stencil reference is compared to the stencil buffer.
+    pub test: Comparison,
+    /// What to do when the stencil test fails.
+    pub op_fail: StencilOp,
+    /// What to do when the stencil test passes, but the depth test fails.
+    pub op_depth_fail: StencilOp,
+    /// What to do when both tests pass.
+    pub op_pass: StencilOp,
+}
+
+/// A comparison for a depth or stencil test, of the incoming value with the stored one.
+///
+/// Only the comparisons the renderer uses are listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// The test always passes.
+    Always,
+    /// The test passes if the incoming value is less than or equal to the stored one.
+    LessEqual,
+    /// The test passes if the incoming value is greater than or equal to the stored one.
+    GreaterEqual,
+    /// The test passes if the values differ.
+    NotEqual,
+}
+
+/// An operation on the stencil buffer.
+///
+/// Only the operations the renderer uses are listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+    /// Leave the value unchanged.
+    Keep,
+    /// Set the value to the stencil reference.
+    Replace,
+    /// Add one, wrapping from 255 to 0.
+    IncrementWrap,
+    /// Subtract one, wrapping from 0 to 255.
+    DecrementWrap,
+}
+
+/// Which faces a pass culls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullFace {
+    /// Draw every face.
+    Nothing,
+    /// Draw only back faces.
+    Front,
+}
+
+/// A single draw: a range of indices, drawn in one pass.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DrawCommand {
+    /// The pass to draw in, which decides the geometry and pipeline state.
+    pub pass: Pass,
+    /// The range of indices to draw, into the index buffer of the pass's geometry.
+    pub indices: Range<u32>,
+}
+
+/// A consumer of draw commands, such as a graphics backend.
+pub trait DrawSink {
+    /// Perform, or record, `command`. Commands are passed in the order they must be drawn in.
+    fn draw(&mut self, command: DrawCommand);
+}
+
+/// A `DrawSink` that keeps the commands it is passed, so that they can be inspected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawRecorder {
+    commands: Vec<DrawCommand>,
+}
+
+impl DrawRecorder {
+    /// Create a recorder with no commands.
+    pub fn new() -> DrawRecorder {
+        DrawRecorder::default()
+    }
+
+    /// The commands recorded so far, in order.
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+
+    /// Forget every command recorded so far.
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+}
+
+impl DrawSink for DrawRecorder {
+    fn draw(&mut self, command: DrawCommand) {
+        self.commands.push(command);
+    }
+}
+
+/// Pass `sink` the commands that drape the polygons in `polyhedron_ranges` and
+/// `bounding_box_ranges` onto the terrain: every polyhedron range, then every bounding box range.
+///
+/// Empty ranges draw nothing, and are skipped.
+pub fn z_fail_commands<S: DrawSink>(
+    sink: &mut S,
+    polyhedron_ranges: &[Range<u32>],
+    bounding_box_ranges: &[Range<u32>],
+) {
+    let non_empty = |range: &&Range<u32>| range.start < range.end;
+
+    for range in polyhedron_ranges.iter().filter(non_empty) {
+        sink.draw(DrawCommand {
+            pass: Pass::Polyhedron,
+            indices: range.clone(),
+        });
+    }
+
+    for range in bounding_box_ranges.iter().filter(non_empty) {
+        sink.draw(DrawCommand {
+            pass: Pass::Cover,
+            indices: range.clone(),
+        });
+    }
+}
+
+/// Pass `sink` the commands that drape `polygons` onto the terrain one at a time: each polygon's
+/// polyhedron range, then its bounding box range, then the next polygon's.
+///
+/// This takes two draws per polygon, but unlike `z_fail_commands`, each polygon's bounding box is
+/// drawn before the next polyhedron, so only the pixels inside its own polyhedron are left for it
+/// to draw. Use this when polygons are drawn differently from one another, such as with
+/// `PolygonStates`, and their bounding boxes may overlap on screen.
+///
+/// Empty ranges draw nothing, and are skipped.
+pub fn z_fail_commands_per_polygon<S, I>(sink: &mut S, polygons: I)
+where
+    S: DrawSink,
+    I: IntoIterator<Item = (Range<u32>, Range<u32>)>,
+{
+    for (polyhedron_range, bounding_box_range) in polygons {
+        let passes = [
+            (Pass::Polyhedron, polyhedron_range),
+            (Pass::Cover, bounding_box_range),
+        ];
+        for (pass, range) in passes.iter().cloned() {
+            if range.start < range.end {
+                sink.draw(DrawCommand {
+                    pass: pass,
+                    indices: range,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn z_fail_commands_draw_polyhedra_first_and_skip_empty_ranges() {
+        let mut recorder = DrawRecorder::new();
+        z_fail_commands(&mut recorder, &[0..6, 6..6, 12..18], &[3..3, 0..9]);
+
+        let polyhedron = |indices| DrawCommand {
+            pass: Pass::Polyhedron,
+            indices: indices,
+        };
+        assert_eq!(
+            recorder.commands(),
+            &[
+                polyhedron(0..6),
+                polyhedron(12..18),
+                DrawCommand {
+                    pass: Pass::Cover,
+                    indices: 0..9,
+                },
+            ]
+        );
+
+        recorder.clear();
+        z_fail_commands(&mut recorder, &[], &[0..0, 3..3]);
+        assert!(recorder.commands().is_empty());
+    }
+
+    #[test]
+    fn z_fail_commands_per_polygon_draw_each_bounding_box_after_its_polyhedron() {
+        let mut recorder = DrawRecorder::new();
+        let polygons = vec![(0..6, 0..9), (6..6, 9..9), (6..12, 9..18)];
+        z_fail_commands_per_polygon(&mut recorder, polygons);
+
+        let command = |pass, indices| DrawCommand {
+            pass: pass,
+            indices: indices,
+        };
+        assert_eq!(
+            recorder.commands(),
+            &[
+                command(Pass::Polyhedron, 0..6),
+                command(Pass::Cover, 0..9),
+                command(Pass::Polyhedron, 6..12),
+                command(Pass::Cover, 9..18),
+            ]
+        );
+    }
+}
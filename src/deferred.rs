@@ -0,0 +1,368 @@
+use gfx;
+
+use commands::Pass;
+use glsl::*;
+use render::*;
+use vertex::Vertex;
+
+/// The most targets `DrapingRenderer::render_deferred` can write to at once.
+pub(crate) const MAX_TARGETS: usize = 4;
+
+/// The format the pipelines are declared with. The real formats are chosen at run time.
+const PLACEHOLDER_FORMAT: gfx::format::Format = gfx::format::Format(
+    gfx::format::SurfaceType::R8_G8_B8_A8,
+    gfx::format::ChannelType::Srgb,
+);
+
+gfx_pipeline!(z_fail_deferred_polyhedron_pipeline {
+    mvp: gfx::Global<[[f32; 4]; 4]> = "u_mvp",
+    states: gfx::TextureSampler<[f32; 4]> = "t_states",
+    use_states: gfx::Global<i32> = "u_use_states",
+    time: gfx::Global<f32> = "u_time",
+    filter_by_time: gfx::Global<i32> = "u_filter_by_time",
+    validity: gfx::TextureSampler<[f32; 4]> = "t_validity",
+    vertex_buffer: gfx::VertexBuffer<Vertex> = (),
+    // The polyhedra only affect the stencil buffer, but the shader still has a color output,
+    // which is bound to the first target and masked off.
+    out_color: gfx::RawRenderTarget = (
+        "o_color",
+        PLACEHOLDER_FORMAT,
+        gfx::state::ColorMask::empty(),
+        None,
+    ),
+    out_depth_stencil: gfx::DepthStencilTarget<gfx::format::DepthStencil> = (
+        gfx::preset::depth::LESS_EQUAL_TEST,
+        polyhedron_stencil(),
+    ),
+});
+
+gfx_pipeline!(z_fail_deferred_bounding_box_pipeline {
+    out_target0: gfx::RawRenderTarget = (
+        "o_target0",
+        PLACEHOLDER_FORMAT,
+        gfx::state::ColorMask::all(),
+        None,
+    ),
+    out_target1: gfx::RawRenderTarget = (
+        "o_target1",
+        PLACEHOLDER_FORMAT,
+        gfx::state::ColorMask::all(),
+        None,
+    ),
+    out_target2: gfx::RawRenderTarget = (
+        "o_target2",
+        PLACEHOLDER_FORMAT,
+        gfx::state::ColorMask::all(),
+        None,
+    ),
+    out_target3: gfx::RawRenderTarget = (
+        "o_target3",
+        PLACEHOLDER_FORMAT,
+        gfx::state::ColorMask::all(),
+        None,
+    ),
+    mvp: gfx::Global<[[f32; 4]; 4]> = "u_mvp",
+    color: gfx::Global<[f32; 4]> = "u_color",
+    highlight_color: gfx::Global<[f32; 4]> = "u_highlight_color",
+    highlight_pulse: gfx::Global<f32> = "u_highlight_pulse",
+    states: gfx::TextureSampler<[f32; 4]> = "t_states",
+    colors: gfx::TextureSampler<[f32; 4]> = "t_colors",
+    fades: gfx::TextureSampler<[f32; 4]> = "t_fades",
+    use_states: gfx::Global<i32> = "u_use_states",
+    time: gfx::Global<f32> = "u_time",
+    filter_by_time: gfx::Global<i32> = "u_filter_by_time",
+    validity: gfx::TextureSampler<[f32; 4]> = "t_validity",
+    target_constants: gfx::Global<[[f32; 4]; 4]> = "u_target_constants",
+    constant_mask: gfx::Global<i32> = "u_constant_mask",
+    multiply_mask: gfx::Global<i32> = "u_multiply_mask",
+    vertex_buffer: gfx::VertexBuffer<Vertex> = (),
+    out_depth_stencil: gfx::DepthStencilTarget<gfx::format::DepthStencil> = (
+        gfx::preset::depth::PASS_TEST,
+        bounding_box_stencil(),
+    ),
+});
+
+/// One of the render targets written by `DrapingRenderer::render_deferred`, such as the albedo
+/// or material ID of a G-buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeferredTarget {
+    /// The format of the target. Its channels must be floating-point or normalized, since the
+    /// shaders write floating-point values.
+    pub format: gfx::format::Format,
+    /// How the polygons are combined with the target's contents.
+    pub blend: BlendMode,
+    /// What the polygons write to the target.
+    pub output: DeferredOutput,
+}
+
+/// What draped polygons write to a `DeferredTarget`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeferredOutput {
+    /// The polygon's color, as passed to `render_deferred` and modified by its `PolygonStates`.
+    Color,
+    /// A constant, such as a material ID. Its alpha is multiplied by the alpha of the polygon's
+    /// color, so that opacity and fades still apply.
+    Constant([f32; 4]),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct DeferredPipelines<R: gfx::Resources> {
+    targets: Vec<DeferredTarget>,
+    polyhedron_pso: gfx::pso::PipelineState<R, z_fail_deferred_polyhedron_pipeline::Meta>,
+    bounding_box_pso: gfx::pso::PipelineState<R, z_fail_deferred_bounding_box_pipeline::Meta>,
+}
+
+impl<R: gfx::Resources> DeferredPipelines<R> {
+    fn new<F: gfx::Factory<R>>(
+        factory: &mut F,
+        variant: ShaderVariant,
+        polyhedron_shaders: &Shaders<R>,
+        targets: &[DeferredTarget],
+    ) -> Result<DeferredPipelines<R>, DrapingError> {
+        assert!(
+            !targets.is_empty() && targets.len() <= MAX_TARGETS,
+            "a deferred renderer needs between 1 and {} targets",
+            MAX_TARGETS
+        );
+
+        let format = targets[0].format;
+        let polyhedron_pso =
+            Self::polyhedron_pso(factory, polyhedron_shaders, variant, None, format)?;
+        Ok(DeferredPipelines {
+            targets: targets.to_vec(),
+            polyhedron_pso: polyhedron_pso,
+            bounding_box_pso: Self::bounding_box_pso(factory, variant, targets)?,
+        })
+    }
+
+    pub(crate) fn set_depth_bias<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        polyhedron_shaders: &Shaders<R>,
+        variant: ShaderVariant,
+        depth_bias: Option<gfx::state::Offset>,
+    ) -> Result<(), DrapingError> {
+        let format = self.targets[0].format;
+        self.polyhedron_pso =
+            Self::polyhedron_pso(factory, polyhedron_shaders, variant, depth_bias, format)?;
+        Ok(())
+    }
+
+    fn polyhedron_pso<F: gfx::Factory<R>>(
+        factory: &mut F,
+        shaders: &Shaders<R>,
+        variant: ShaderVariant,
+        depth_bias: Option<gfx::state::Offset>,
+        format: gfx::format::Format,
+    ) -> Result<
+        gfx::pso::PipelineState<R, z_fail_deferred_polyhedron_pipeline::Meta>,
+        DrapingError,
+    > {
+        let init = z_fail_deferred_polyhedron_pipeline::Init {
+            out_color: ("o_color", format, gfx::state::ColorMask::empty(), None),
+            out_depth_stencil: (variant.depth.polyhedron_test(), polyhedron_stencil()),
+            ..z_fail_deferred_polyhedron_pipeline::new()
+        };
+
+        let rasterizer = gfx::state::Rasterizer {
+            offset: depth_bias,
+            ..rasterizer(Pass::Polyhedron, variant.depth)
+        };
+
+        shaders.pipeline(factory, rasterizer, init)
+    }
+
+    fn bounding_box_pso<F: gfx::Factory<R>>(
+        factory: &mut F,
+        variant: ShaderVariant,
+        targets: &[DeferredTarget],
+    ) -> Result<
+        gfx::pso::PipelineState<R, z_fail_deferred_bounding_box_pipeline::Meta>,
+        DrapingError,
+    > {
+        // The fragment shader only declares as many outputs as there are targets, so that the
+        // unused targets of the pipeline aren't linked to anything.
+        let shaders = variant.compile(
+            factory,
+            &format!("bounding_box-targets{}", targets.len()),
+            BOUNDING_BOX_VERT,
+            BOUNDING_BOX_FRAG,
+            &[&format!("TARGET_COUNT {}", targets.len())],
+        )?;
+
+        let rasterizer = rasterizer(Pass::Cover, variant.depth);
+
+        let target_init = |index: usize, name| {
+            let (format, blend) = targets
+                .get(index)
+                .map_or((PLACEHOLDER_FORMAT, None), |target| {
+                    (target.format, Some(target.blend.state()))
+                });
+            (name, format, gfx::state::ColorMask::all(), blend)
+        };
+
+        let init = z_fail_deferred_bounding_box_pipeline::Init {
+            out_target0: target_init(0, "o_target0"),
+            out_target1: target_init(1, "o_target1"),
+            out_target2: target_init(2, "o_target2"),
+            out_target3: target_init(3, "o_target3"),
+            ..z_fail_deferred_bounding_box_pipeline::new()
+        };
+
+        shaders.pipeline(factory, rasterizer, init)
+    }
+}
+
+impl<R: gfx::Resources> DrapingRenderer<R> {
+    /// Set up a renderer which, besides everything `new` supports, can draw polygons into the
+    /// G-buffer of a deferred renderer with `render_deferred`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `targets` is empty or has more than four targets, or if the renderer's shaders or
+    /// pipelines can't be created; see `try_with_deferred_targets`.
+    pub fn with_deferred_targets<F: gfx::Factory<R>>(
+        factory: &mut F,
+        depth: DepthConvention,
+        targets: &[DeferredTarget],
+    ) -> DrapingRenderer<R> {
+        Self::try_with_deferred_targets(factory, depth, targets)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `with_deferred_targets`, but returns an error rather than panicking if the renderer's
+    /// shaders or pipelines can't be created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `targets` is empty or has more than four targets.
+    pub fn try_with_deferred_targets<F: gfx::Factory<R>>(
+        factory: &mut F,
+        depth: DepthConvention,
+        targets: &[DeferredTarget],
+    ) -> Result<DrapingRenderer<R>, DrapingError> {
+        let mut renderer = DrapingRenderer::try_new(factory, depth)?;
+        renderer.deferred = Some(DeferredPipelines::new(
+            factory,
+            renderer.variant,
+            &renderer.polyhedron_shaders,
+            targets,
+        )?);
+        Ok(renderer)
+    }
+
+    /// Render polygons into the targets passed to `with_deferred_targets`, before lighting.
+    ///
+    /// `render_targets` should have one view for each of those targets, in the same order. The
+    /// polygons in `params` are otherwise drawn as by `render`, with its states and
+    /// time if it has them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this renderer wasn't created with `with_deferred_targets`, or if
+    /// `render_targets` doesn't have exactly one view for each of its targets, including if it is
+    /// empty.
+    pub fn render_deferred<C: gfx::CommandBuffer<R>>(
+        &self,
+        encoder: &mut gfx::Encoder<R, C>,
+        render_targets: &[gfx::handle::RawRenderTargetView<R>],
+        depth_stencil_target: gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        params: RenderParams<R>,
+    ) {
+        let RenderParams {
+            mvp,
+            color,
+            buffer,
+            indices,
+            states,
+            time,
+        } = params;
+        let deferred = self.deferred
+            .as_ref()
+            .expect("this renderer was not created with deferred targets");
+        assert!(
+            render_targets.len() == deferred.targets.len(),
+            "expected {} render targets, one for each deferred target, but got {}",
+            deferred.targets.len(),
+            render_targets.len()
+        );
+
+        let state_params = self.state_params(states, color);
+        let use_states = states.is_some() as i32;
+        let filter_by_time = time.is_some() as i32;
+        let time = time.unwrap_or(0.0);
+
+        let mut target_constants = [[0.0; 4]; 4];
+        let (mut constant_mask, mut multiply_mask) = (0, 0);
+        for (index, target) in deferred.targets.iter().enumerate() {
+            if let DeferredOutput::Constant(constant) = target.output {
+                target_constants[index] = constant;
+                constant_mask |= 1 << index;
+            }
+
+            if target.blend == BlendMode::Multiply {
+                multiply_mask |= 1 << index;
+            }
+        }
+
+        // Unused targets aren't linked to the shader, so any view will do for them. There is at
+        // least one view, since there is at least one deferred target.
+        let render_target = |index: usize| {
+            render_targets
+                .get(index)
+                .unwrap_or(&render_targets[0])
+                .clone()
+        };
+
+        let polyhedron_data = z_fail_deferred_polyhedron_pipeline::Data {
+            mvp: mvp,
+            states: (state_params.states.clone(), self.sampler.clone()),
+            use_states: use_states,
+            time: time,
+            filter_by_time: filter_by_time,
+            validity: (buffer.validity_view.clone(), self.sampler.clone()),
+            vertex_buffer: buffer.polyhedron_vertex_buffer.clone(),
+            out_color: render_target(0),
+            out_depth_stencil: (depth_stencil_target.clone(), (0, 0)),
+        };
+
+        let bounding_box_data = z_fail_deferred_bounding_box_pipeline::Data {
+            out_target0: render_target(0),
+            out_target1: render_target(1),
+            out_target2: render_target(2),
+            out_target3: render_target(3),
+            mvp: mvp,
+            color: color,
+            highlight_color: state_params.highlight_color,
+            highlight_pulse: state_params.highlight_pulse,
+            states: (state_params.states, self.sampler.clone()),
+            colors: (state_params.colors, self.sampler.clone()),
+            fades: (state_params.fades, self.sampler.clone()),
+            use_states: use_states,
+            time: time,
+            filter_by_time: filter_by_time,
+            validity: (buffer.validity_view.clone(), self.sampler.clone()),
+            target_constants: target_constants,
+            constant_mask: constant_mask,
+            multiply_mask: multiply_mask,
+            vertex_buffer: buffer.bounding_box_vertex_buffer.clone(),
+            out_depth_stencil: (depth_stencil_target, (0, 0)),
+        };
+
+        let mut sink = EncoderSink {
+            encoder: encoder,
+            indices: indices,
+            polyhedron_pso: &deferred.polyhedron_pso,
+            polyhedron_data: &polyhedron_data,
+            bounding_box_pso: &deferred.bounding_box_pso,
+            bounding_box_data: &bounding_box_data,
+        };
+
+        // As in `DrapingRenderer::render`, polygons with states are drawn one at a time.
+        if states.is_some() {
+            indices.draw_commands_per_polygon(&mut sink);
+        } else {
+            indices.draw_commands(&mut sink);
+        }
+    }
+}
@@ -0,0 +1,850 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+use std::slice;
+
+use gfx;
+
+use polygon::*;
+use render::*;
+use surface::{Ellipsoid, Globe, Surface, UpAxis};
+use triangulate::CapTriangulation;
+use vertex::Vertex;
+
+const MAGIC: &[u8; 8] = b"GFXDRAPE";
+const VERSION: u32 = 6;
+const HEADER_LENGTH: usize = 32;
+
+/// A `PolygonBuffer` and named sets of `PolygonBufferIndices`, stored in a compact binary format.
+///
+/// Files in this format are laid out so that their vertex and index arrays can be used in place:
+/// parsing a file checks its arrays without copying them, and they are uploaded to the GPU
+/// straight from the bytes you pass to `parse`. Combined with memory mapping (see the `mmap`
+/// feature), this makes loading even very large buffers cheap.
+///
+/// # Format
+///
+/// All numbers are little-endian, and every field starts at an offset that is a multiple of 4
+/// from the beginning of the file. The file starts with a 32-byte header:
+///
+/// | Offset | Type      | Contents                                                      |
+/// |--------|-----------|---------------------------------------------------------------|
+/// | 0      | `[u8; 8]` | The magic bytes `GFXDRAPE`.                                   |
+/// | 8      | `u32`     | The format version, currently 6.                              |
+/// | 12     | `u32`     | Reserved, always 0.                                           |
+/// | 16     | `u64`     | The length of the payload, i.e. everything after the header. |
+/// | 24     | `u32`     | The CRC-32 of the payload.                                    |
+/// | 28     | `u32`     | The CRC-32 of the first 28 bytes of the header.               |
+///
+/// The payload starts with a 40-byte description of the buffer's `Surface`:
+///
+/// | Offset | Type  | Contents                                                     |
+/// |--------|-------|--------------------------------------------------------------|
+/// | 0      | `u32` | 0 for `Surface::Plane`, 1 for `Surface::Globe`.              |
+/// | 4      | `u32` | For planes, the up-axis: 0 for X, 1 for Y and 2 for Z.       |
+/// | 8      | `f64` | For globes, the ellipsoid's equatorial radius.               |
+/// | 16     | `f64` | For globes, the ellipsoid's polar radius.                    |
+/// | 24     | `f32` | For globes, the minimum height.                              |
+/// | 28     | `f32` | For globes, the maximum height.                              |
+/// | 32     | `f32` | For globes, the maximum edge length.                         |
+/// | 36     | `u32` | Reserved, always 0.                                          |
+///
+/// Then come four `u32`s: the number of polyhedron vertices, the number of bounding-box vertices,
+/// the number of index sets, and the number of polygons in the buffer. They are followed by the
+/// polyhedron vertices and then the bounding-box vertices, each vertex being three `f32`s holding
+/// its position and a `u32` holding the `PolygonId` of the polygon it belongs to. Then, for each
+/// polygon in order of ID, come two `f32`s holding the start and end of its validity interval
+/// (`f32::MIN` and `f32::MAX` for polygons drawn at all times).
+///
+/// Strings are stored as a `u32` byte length, followed by their UTF-8 bytes padded with zeroes to
+/// a multiple of 4 bytes.
+///
+/// Next come the buffer's `CapTriangulation`, as a `u32` that is 0 for `Fan` and 1 for
+/// `EarClipping`, and the number of polygons with attributes, as a `u32`. Each of those polygons
+/// is stored, in order of ID, as a `u32` holding its `PolygonId` and a `u32` holding its number of
+/// attributes, followed by each attribute in order of name:
+///
+/// * The attribute's name, as a string.
+/// * A `u32` that is 0 for `AttributeValue::Number`, 1 for `Text` and 2 for `Bool`.
+/// * The value: an `f64` for numbers, a string for text, and a `u32` that is 0 or 1 for booleans.
+///
+/// Finally, each index set is stored as:
+///
+/// * The set's name, as a string.
+/// * Three `u32`s: the number of polyhedron indices, bounding-box indices, and polygons.
+/// * The polyhedron indices and then the bounding-box indices, each a `u32`.
+/// * For each polygon, a `u32` holding its `PolygonId`, then six `f32`s holding the minimum and
+///   maximum corners of its bounding-box prism, followed by four `u32`s holding the start and end
+///   of its polyhedron indices and then of its bounding-box indices.
+///
+/// Files with a different version, or whose checksums do not match their contents, are rejected
+/// by `parse`. This makes it safe to cache files across upgrades of this crate. So are files whose
+/// indices or polygon IDs are out of range, so that no file can make the GPU read out of bounds.
+#[derive(Clone, Debug)]
+pub struct BufferFile<'a> {
+    surface: Surface,
+    num_polygons: u32,
+    polyhedron_vertices: Cow<'a, [Vertex]>,
+    bounding_box_vertices: Cow<'a, [Vertex]>,
+    validities: Cow<'a, [[f32; 2]]>,
+    cap_triangulation: CapTriangulation,
+    attributes: BTreeMap<PolygonId, BTreeMap<String, AttributeValue>>,
+    index_sets: Vec<IndexSet<'a>>,
+}
+
+#[derive(Clone, Debug)]
+struct IndexSet<'a> {
+    name: &'a str,
+    polyhedron_indices: Cow<'a, [u32]>,
+    bounding_box_indices: Cow<'a, [u32]>,
+    polygons: Vec<PolygonRange>,
+}
+
+impl<'a> BufferFile<'a> {
+    /// Write `buffer` and a set of named indices into it to `writer`.
+    pub fn write<W: Write>(
+        writer: &mut W,
+        buffer: &PolygonBuffer,
+        index_sets: &[(&str, &PolygonBufferIndices)],
+    ) -> io::Result<()> {
+        let mut payload = Vec::new();
+        Self::write_surface(&mut payload, &buffer.surface);
+        write_u32(&mut payload, buffer.polyhedron_vertices.len() as u32);
+        write_u32(&mut payload, buffer.bounding_box_vertices.len() as u32);
+        write_u32(&mut payload, index_sets.len() as u32);
+        write_u32(&mut payload, buffer.num_polygons);
+
+        for vertex in buffer.polyhedron_vertices.iter().chain(&buffer.bounding_box_vertices) {
+            for &coordinate in &vertex.position {
+                write_f32(&mut payload, coordinate);
+            }
+            write_u32(&mut payload, vertex.polygon_id);
+        }
+
+        for validity in &buffer.validities {
+            write_f32(&mut payload, validity[0]);
+            write_f32(&mut payload, validity[1]);
+        }
+
+        write_u32(&mut payload, match buffer.cap_triangulation() {
+            CapTriangulation::Fan => 0,
+            CapTriangulation::EarClipping => 1,
+        });
+
+        write_u32(&mut payload, buffer.attributes.len() as u32);
+        for (id, attributes) in &buffer.attributes {
+            write_u32(&mut payload, id.0);
+            write_u32(&mut payload, attributes.len() as u32);
+
+            for (name, value) in attributes {
+                write_str(&mut payload, name);
+                match *value {
+                    AttributeValue::Number(number) => {
+                        write_u32(&mut payload, 0);
+                        write_f64(&mut payload, number);
+                    }
+                    AttributeValue::Text(ref text) => {
+                        write_u32(&mut payload, 1);
+                        write_str(&mut payload, text);
+                    }
+                    AttributeValue::Bool(boolean) => {
+                        write_u32(&mut payload, 2);
+                        write_u32(&mut payload, boolean as u32);
+                    }
+                }
+            }
+        }
+
+        for &(name, indices) in index_sets {
+            write_str(&mut payload, name);
+
+            write_u32(&mut payload, indices.polyhedron_indices.len() as u32);
+            write_u32(&mut payload, indices.bounding_box_indices.len() as u32);
+            write_u32(&mut payload, indices.polygons.len() as u32);
+
+            for &index in indices.polyhedron_indices.iter().chain(&indices.bounding_box_indices) {
+                write_u32(&mut payload, index);
+            }
+
+            for polygon in &indices.polygons {
+                write_u32(&mut payload, polygon.id.0);
+                for &coordinate in polygon.bounds[0].iter().chain(&polygon.bounds[1]) {
+                    write_f32(&mut payload, coordinate);
+                }
+
+                write_u32(&mut payload, polygon.polyhedron_indices.start);
+                write_u32(&mut payload, polygon.polyhedron_indices.end);
+                write_u32(&mut payload, polygon.bounding_box_indices.start);
+                write_u32(&mut payload, polygon.bounding_box_indices.end);
+            }
+        }
+
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.extend_from_slice(MAGIC);
+        write_u32(&mut header, VERSION);
+        write_u32(&mut header, 0);
+        write_u32(&mut header, payload.len() as u32);
+        write_u32(&mut header, (payload.len() as u64 >> 32) as u32);
+        write_u32(&mut header, crc32(&payload));
+        let header_checksum = crc32(&header);
+        write_u32(&mut header, header_checksum);
+
+        writer.write_all(&header)?;
+        writer.write_all(&payload)
+    }
+
+    /// Parse a file previously written with `write`, verifying its checksums, and that every
+    /// index and polygon ID in it is in range.
+    ///
+    /// The arrays are used in place if `bytes` is aligned to at least 4 bytes, as memory-mapped
+    /// files are, and the platform is little-endian. Otherwise, such as for bytes from
+    /// `include_bytes!`, they are copied.
+    pub fn parse(bytes: &'a [u8]) -> Result<BufferFile<'a>, FileError> {
+        Self::parse_header(bytes, true)?;
+        Self::parse_payload(&bytes[HEADER_LENGTH..], true)
+    }
+
+    /// Parse a file whose payload is already known to be intact.
+    ///
+    /// The header is still validated, but the payload checksum and the ranges of its indices --
+    /// which require reading the whole file -- are not.
+    #[cfg(feature = "mmap")]
+    fn parse_trusted(bytes: &'a [u8]) -> Result<BufferFile<'a>, FileError> {
+        Self::parse_header(bytes, false)?;
+        Self::parse_payload(&bytes[HEADER_LENGTH..], false)
+    }
+
+    /// The surface of this file's buffer.
+    pub fn surface(&self) -> Surface {
+        self.surface
+    }
+
+    /// The number of vertices in this file's buffer, as `(polyhedron_vertices,
+    /// bounding_box_vertices)`.
+    pub fn vertex_counts(&self) -> (usize, usize) {
+        (
+            self.polyhedron_vertices.len(),
+            self.bounding_box_vertices.len(),
+        )
+    }
+
+    /// The names of the index sets in this file, in the order they were written.
+    pub fn index_set_names(&self) -> Vec<&'a str> {
+        self.index_sets.iter().map(|set| set.name).collect()
+    }
+
+    /// Prepare this file's buffer for rendering, as if with `RenderablePolygonBuffer::new`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer's vertex buffers or validity texture can't be created; see
+    /// `try_buffer_as_renderable`.
+    pub fn buffer_as_renderable<F: gfx::Factory<R>, R: gfx::Resources>(
+        &self,
+        factory: &mut F,
+    ) -> RenderablePolygonBuffer<R> {
+        self.try_buffer_as_renderable(factory).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `buffer_as_renderable`, but returns an error rather than panicking if the buffer's
+    /// vertex buffers or validity texture can't be created.
+    pub fn try_buffer_as_renderable<F: gfx::Factory<R>, R: gfx::Resources>(
+        &self,
+        factory: &mut F,
+    ) -> Result<RenderablePolygonBuffer<R>, DrapingError> {
+        RenderablePolygonBuffer::from_vertices(
+            factory,
+            &self.polyhedron_vertices,
+            &self.bounding_box_vertices,
+            &self.validities,
+        )
+    }
+
+    /// Prepare the index set called `name` for rendering, as if with
+    /// `RenderablePolygonIndices::new`.
+    ///
+    /// Returns `None` if there is no index set with that name.
+    pub fn indices_as_renderable<F: gfx::Factory<R>, R: gfx::Resources>(
+        &self,
+        factory: &mut F,
+        name: &str,
+    ) -> Option<RenderablePolygonIndices<R>> {
+        self.index_set(name).map(|set| {
+            RenderablePolygonIndices::from_slices(
+                factory,
+                &set.polyhedron_indices,
+                &set.bounding_box_indices,
+                set.polygons.clone(),
+            )
+        })
+    }
+
+    /// Copy this file's buffer into a `PolygonBuffer`, for instance to add more polygons to it.
+    ///
+    /// The returned buffer has the attributes and `CapTriangulation` the file was written with.
+    pub fn to_buffer(&self) -> PolygonBuffer {
+        let mut buffer = PolygonBuffer::with_surface(self.surface);
+        buffer.polyhedron_vertices = self.polyhedron_vertices.to_vec();
+        buffer.bounding_box_vertices = self.bounding_box_vertices.to_vec();
+        buffer.num_polygons = self.num_polygons;
+        buffer.validities = self.validities.to_vec();
+        buffer.attributes = self.attributes.clone();
+        buffer.set_cap_triangulation(self.cap_triangulation);
+        buffer
+    }
+
+    /// Copy the index set called `name` into a `PolygonBufferIndices`.
+    ///
+    /// Returns `None` if there is no index set with that name.
+    pub fn to_indices(&self, name: &str) -> Option<PolygonBufferIndices> {
+        self.index_set(name).map(|set| {
+            PolygonBufferIndices::from_parts(
+                set.polyhedron_indices.to_vec(),
+                set.bounding_box_indices.to_vec(),
+                set.polygons.clone(),
+            )
+        })
+    }
+
+    fn index_set(&self, name: &str) -> Option<&IndexSet<'a>> {
+        self.index_sets.iter().find(|set| set.name == name)
+    }
+
+    fn write_surface(payload: &mut Vec<u8>, surface: &Surface) {
+        match *surface {
+            Surface::Plane(up_axis) => {
+                write_u32(payload, 0);
+                write_u32(payload, match up_axis {
+                    UpAxis::X => 0,
+                    UpAxis::Y => 1,
+                    UpAxis::Z => 2,
+                });
+                payload.extend_from_slice(&[0; 32]);
+            }
+            Surface::Globe(ref globe) => {
+                write_u32(payload, 1);
+                write_u32(payload, 0);
+                write_f64(payload, globe.ellipsoid.equatorial_radius);
+                write_f64(payload, globe.ellipsoid.polar_radius);
+                write_f32(payload, globe.min_height);
+                write_f32(payload, globe.max_height);
+                write_f32(payload, globe.max_edge_length);
+                write_u32(payload, 0);
+            }
+        }
+    }
+
+    fn parse_header(bytes: &[u8], verify_payload: bool) -> Result<(), FileError> {
+        if bytes.len() < HEADER_LENGTH || bytes[..8] != MAGIC[..] {
+            return Err(FileError::NotABufferFile);
+        }
+
+        let mut header = Reader::new(&bytes[8..HEADER_LENGTH]);
+        let version = header.u32()?;
+        header.u32()?;
+        let payload_length = header.u32()? as u64 | (header.u32()? as u64) << 32;
+        let payload_checksum = header.u32()?;
+        let header_checksum = header.u32()?;
+
+        if header_checksum != crc32(&bytes[..HEADER_LENGTH - 4]) {
+            return Err(FileError::ChecksumMismatch);
+        }
+
+        if version != VERSION {
+            return Err(FileError::UnsupportedVersion(version));
+        }
+
+        let payload = &bytes[HEADER_LENGTH..];
+        if payload.len() as u64 != payload_length {
+            return Err(FileError::Truncated);
+        }
+
+        if verify_payload && payload_checksum != crc32(payload) {
+            return Err(FileError::ChecksumMismatch);
+        }
+
+        Ok(())
+    }
+
+    fn parse_payload(payload: &'a [u8], verify: bool) -> Result<BufferFile<'a>, FileError> {
+        let mut reader = Reader::new(payload);
+
+        let surface = match reader.u32()? {
+            0 => {
+                let up_axis = match reader.u32()? {
+                    0 => UpAxis::X,
+                    1 => UpAxis::Y,
+                    2 => UpAxis::Z,
+                    _ => return Err(FileError::Malformed),
+                };
+                reader.bytes(32)?;
+
+                Surface::Plane(up_axis)
+            }
+            1 => {
+                reader.u32()?;
+                let ellipsoid = Ellipsoid::new(reader.f64()?, reader.f64()?);
+                let globe = Globe::new(ellipsoid, reader.f32()?, reader.f32()?, reader.f32()?);
+                reader.u32()?;
+
+                Surface::Globe(globe)
+            }
+            _ => return Err(FileError::Malformed),
+        };
+
+        let num_polyhedron_vertices = reader.u32()? as usize;
+        let num_bounding_box_vertices = reader.u32()? as usize;
+        let num_index_sets = reader.u32()? as usize;
+        let num_polygons = reader.u32()?;
+
+        let polyhedron_vertices: Cow<[Vertex]> = reader.array(num_polyhedron_vertices)?;
+        let bounding_box_vertices: Cow<[Vertex]> = reader.array(num_bounding_box_vertices)?;
+
+        let validities: Cow<[[f32; 2]]> = reader.array(num_polygons as usize)?;
+
+        let mut vertices = polyhedron_vertices.iter().chain(bounding_box_vertices.iter());
+        if verify && vertices.any(|vertex| vertex.polygon_id >= num_polygons) {
+            return Err(FileError::Malformed);
+        }
+
+        let cap_triangulation = match reader.u32()? {
+            0 => CapTriangulation::Fan,
+            1 => CapTriangulation::EarClipping,
+            _ => return Err(FileError::Malformed),
+        };
+
+        let mut attributes = BTreeMap::new();
+        for _ in 0..reader.u32()? {
+            let id = reader.u32()?;
+            if id >= num_polygons {
+                return Err(FileError::Malformed);
+            }
+
+            let mut polygon_attributes = BTreeMap::new();
+            for _ in 0..reader.u32()? {
+                let name = reader.str()?.to_owned();
+                let value = match reader.u32()? {
+                    0 => AttributeValue::Number(reader.f64()?),
+                    1 => AttributeValue::Text(reader.str()?.to_owned()),
+                    2 => match reader.u32()? {
+                        0 => AttributeValue::Bool(false),
+                        1 => AttributeValue::Bool(true),
+                        _ => return Err(FileError::Malformed),
+                    },
+                    _ => return Err(FileError::Malformed),
+                };
+                polygon_attributes.insert(name, value);
+            }
+            attributes.insert(PolygonId(id), polygon_attributes);
+        }
+
+        // The counts aren't trusted to size allocations, but every entry takes at least 4 bytes.
+        let mut index_sets = Vec::with_capacity(num_index_sets.min(reader.remaining() / 4));
+        for _ in 0..num_index_sets {
+            let name = reader.str()?;
+
+            let num_polyhedron_indices = reader.u32()? as usize;
+            let num_bounding_box_indices = reader.u32()? as usize;
+            let num_set_polygons = reader.u32()? as usize;
+
+            let polyhedron_indices: Cow<[u32]> = reader.array(num_polyhedron_indices)?;
+            let bounding_box_indices: Cow<[u32]> = reader.array(num_bounding_box_indices)?;
+
+            let out_of_range = |indices: &[u32], num_vertices: usize| {
+                indices.iter().any(|&index| index as usize >= num_vertices)
+            };
+            if verify &&
+                (out_of_range(&polyhedron_indices, num_polyhedron_vertices) ||
+                     out_of_range(&bounding_box_indices, num_bounding_box_vertices))
+            {
+                return Err(FileError::Malformed);
+            }
+
+            let mut polygons = Vec::with_capacity(num_set_polygons.min(reader.remaining() / 4));
+            for _ in 0..num_set_polygons {
+                let id = reader.u32()?;
+                if id >= num_polygons {
+                    return Err(FileError::Malformed);
+                }
+
+                let min = [reader.f32()?, reader.f32()?, reader.f32()?];
+                let max = [reader.f32()?, reader.f32()?, reader.f32()?];
+                let polyhedron = reader.range(num_polyhedron_indices)?;
+                let bounding_box = reader.range(num_bounding_box_indices)?;
+
+                polygons.push(PolygonRange {
+                    id: PolygonId(id),
+                    bounds: [min, max],
+                    polyhedron_indices: polyhedron,
+                    bounding_box_indices: bounding_box,
+                });
+            }
+
+            index_sets.push(IndexSet {
+                name: name,
+                polyhedron_indices: polyhedron_indices,
+                bounding_box_indices: bounding_box_indices,
+                polygons: polygons,
+            });
+        }
+
+        Ok(BufferFile {
+            surface: surface,
+            num_polygons: num_polygons,
+            polyhedron_vertices: polyhedron_vertices,
+            bounding_box_vertices: bounding_box_vertices,
+            validities: validities,
+            cap_triangulation: cap_triangulation,
+            attributes: attributes,
+            index_sets: index_sets,
+        })
+    }
+}
+
+/// A `BufferFile` that has been memory-mapped from disk.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MappedBufferFile {
+    mmap: ::memmap::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedBufferFile {
+    /// Memory-map the file at `path`, and verify that it is a valid `BufferFile`.
+    ///
+    /// The file must not be modified while it is mapped.
+    pub fn open<P: AsRef<::std::path::Path>>(path: P) -> Result<MappedBufferFile, FileError> {
+        let file = ::std::fs::File::open(path)?;
+        let mmap = unsafe { ::memmap::Mmap::map(&file)? };
+        BufferFile::parse(&mmap)?;
+
+        Ok(MappedBufferFile { mmap: mmap })
+    }
+
+    /// Get the contents of the mapped file.
+    ///
+    /// This doesn't verify the file's checksums again, so it is cheap to call, though any polygon
+    /// attributes are copied out of the file.
+    pub fn file(&self) -> BufferFile<'_> {
+        // The file was already fully validated when it was opened.
+        BufferFile::parse_trusted(&self.mmap).unwrap()
+    }
+}
+
+/// The error returned when a `BufferFile` can't be read.
+#[derive(Debug)]
+pub enum FileError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file does not start with the `BufferFile` magic bytes.
+    NotABufferFile,
+    /// The file was written in a different version of the format.
+    UnsupportedVersion(u32),
+    /// The file is shorter than its header says it should be.
+    Truncated,
+    /// The file's contents do not match its checksums; it is probably corrupt.
+    ChecksumMismatch,
+    /// The file's contents are inconsistent, despite matching its checksums.
+    Malformed,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileError::Io(ref error) => write!(f, "could not read buffer file: {}", error),
+            FileError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "buffer file has version {}, but only version {} is supported",
+                    version,
+                    VERSION
+                )
+            }
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl Error for FileError {
+    fn description(&self) -> &str {
+        self.message()
+    }
+
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            FileError::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl FileError {
+    fn message(&self) -> &'static str {
+        match *self {
+            FileError::Io(_) => "could not read buffer file",
+            FileError::NotABufferFile => "not a buffer file",
+            FileError::UnsupportedVersion(_) => "unsupported buffer file version",
+            FileError::Truncated => "buffer file is truncated",
+            FileError::ChecksumMismatch => "buffer file checksum mismatch",
+            FileError::Malformed => "buffer file is malformed",
+        }
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(error: io::Error) -> FileError {
+        FileError::Io(error)
+    }
+}
+
+/// Marker for types which can be read in place from a file's bytes.
+///
+/// # Safety
+///
+/// Implementors must consist solely of 4-byte numbers, with no padding, for which every bit
+/// pattern is valid. `Reader::array` relies on this to reinterpret bytes as the type, and to
+/// convert them from little-endian one 4-byte word at a time.
+unsafe trait Plain: Copy {}
+unsafe impl Plain for u32 {}
+unsafe impl Plain for Vertex {}
+unsafe impl Plain for [f32; 2] {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes: bytes }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], FileError> {
+        if self.bytes.len() < length {
+            return Err(FileError::Truncated);
+        }
+
+        let (bytes, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn str(&mut self) -> Result<&'a str, FileError> {
+        let length = self.u32()? as usize;
+        let padded_length = length.checked_add(3).ok_or(FileError::Malformed)? / 4 * 4;
+        let bytes = self.bytes(padded_length)?;
+        ::std::str::from_utf8(&bytes[..length]).map_err(|_| FileError::Malformed)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn u32(&mut self) -> Result<u32, FileError> {
+        let bytes = self.bytes(4)?;
+        Ok(
+            bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 |
+                (bytes[3] as u32) << 24,
+        )
+    }
+
+    fn f32(&mut self) -> Result<f32, FileError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, FileError> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(f64::from_bits(low | high << 32))
+    }
+
+    fn range(&mut self, len: usize) -> Result<Range<u32>, FileError> {
+        let (start, end) = (self.u32()?, self.u32()?);
+        if start > end || end as usize > len {
+            return Err(FileError::Malformed);
+        }
+
+        Ok(start..end)
+    }
+
+    /// Read `len` values of type `T`, in place if the bytes are suitably aligned and in the
+    /// platform's byte order, and copied otherwise.
+    fn array<T: Plain>(&mut self, len: usize) -> Result<Cow<'a, [T]>, FileError> {
+        let length = len.checked_mul(mem::size_of::<T>())
+            .ok_or(FileError::Malformed)?;
+        let bytes = self.bytes(length)?;
+
+        let aligned = bytes.as_ptr().align_offset(mem::align_of::<T>()) == 0;
+        if cfg!(target_endian = "little") && aligned {
+            // This is sound because `T` is plain data of the right size, and the bytes are
+            // suitably aligned and laid out in the platform's byte order.
+            return Ok(Cow::Borrowed(
+                unsafe { slice::from_raw_parts(bytes.as_ptr() as *const T, len) },
+            ));
+        }
+
+        let words: Vec<u32> = bytes
+            .chunks(4)
+            .map(|word| Reader::new(word).u32().unwrap())
+            .collect();
+
+        // This is sound because `T` is made of `len * size_of::<T>() / 4` 4-byte words, which
+        // `words` holds in the platform's byte order.
+        let mut values = Vec::with_capacity(len);
+        unsafe {
+            ptr::copy_nonoverlapping(
+                words.as_ptr() as *const u8,
+                values.as_mut_ptr() as *mut u8,
+                length,
+            );
+            values.set_len(len);
+        }
+
+        Ok(Cow::Owned(values))
+    }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&[
+        value as u8,
+        (value >> 8) as u8,
+        (value >> 16) as u8,
+        (value >> 24) as u8,
+    ]);
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    write_u32(bytes, value.len() as u32);
+    bytes.extend_from_slice(value.as_bytes());
+    while bytes.len() & 3 != 0 {
+        bytes.push(0);
+    }
+}
+
+fn write_f32(bytes: &mut Vec<u8>, value: f32) {
+    write_u32(bytes, value.to_bits());
+}
+
+fn write_f64(bytes: &mut Vec<u8>, value: f64) {
+    let bits = value.to_bits();
+    write_u32(bytes, bits as u32);
+    write_u32(bytes, (bits >> 32) as u32);
+}
+
+/// The lookup table for `crc32`, holding the CRC of each byte value.
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The CRC-32 (as used by zlib and PNG) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use polygon::Polygon;
+
+    use super::*;
+
+    fn square_buffer() -> (PolygonBuffer, PolygonBufferIndices) {
+        let points = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)];
+        let mut buffer = PolygonBuffer::new();
+        let polygon = Polygon::new([(0.0, 0.0), (1.0, 1.0)], points).with_validity(1.0, 2.0);
+        let indices = buffer.add(&polygon);
+        (buffer, indices)
+    }
+
+    fn written(buffer: &PolygonBuffer, indices: &PolygonBufferIndices) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        BufferFile::write(&mut bytes, buffer, &[("all", indices)]).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn files_round_trip_from_unaligned_bytes() {
+        let (buffer, indices) = square_buffer();
+        let bytes = written(&buffer, &indices);
+
+        // Offset by a byte, so that at most one of the two parses reads in place.
+        let mut shifted = vec![0];
+        shifted.extend_from_slice(&bytes);
+
+        for bytes in &[&bytes[..], &shifted[1..]] {
+            let file = BufferFile::parse(bytes).unwrap();
+            let parsed = file.to_indices("all").unwrap();
+            assert_eq!(parsed.polyhedron_indices, indices.polyhedron_indices);
+            assert_eq!(parsed.bounding_box_indices, indices.bounding_box_indices);
+
+            let positions = |buffer: &PolygonBuffer| -> Vec<_> {
+                buffer.polyhedron_vertices.iter().map(|vertex| vertex.position).collect()
+            };
+            assert_eq!(positions(&file.to_buffer()), positions(&buffer));
+            assert_eq!(file.to_buffer().validities, vec![[1.0, 2.0]]);
+        }
+    }
+
+    #[test]
+    fn files_round_trip_attributes_and_cap_triangulation() {
+        let (mut buffer, indices) = square_buffer();
+        buffer.set_cap_triangulation(CapTriangulation::EarClipping);
+        buffer.set_attribute(PolygonId(0), "name", "square");
+        buffer.set_attribute(PolygonId(0), "area", 1.0);
+        buffer.set_attribute(PolygonId(0), "closed", true);
+        let bytes = written(&buffer, &indices);
+
+        let parsed = BufferFile::parse(&bytes).unwrap().to_buffer();
+        assert_eq!(parsed.cap_triangulation(), CapTriangulation::EarClipping);
+        assert_eq!(parsed.attributes, buffer.attributes);
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn files_with_out_of_range_indices_are_rejected() {
+        let (buffer, mut indices) = square_buffer();
+        indices.polyhedron_indices[0] = buffer.polyhedron_vertices.len() as u32;
+        let bytes = written(&buffer, &indices);
+
+        assert!(matches!(BufferFile::parse(&bytes), Err(FileError::Malformed)));
+    }
+
+    #[test]
+    fn files_with_out_of_range_polygon_ids_are_rejected() {
+        let (mut buffer, indices) = square_buffer();
+        buffer.bounding_box_vertices[0].polygon_id = buffer.num_polygons;
+        let bytes = written(&buffer, &indices);
+
+        assert!(matches!(BufferFile::parse(&bytes), Err(FileError::Malformed)));
+    }
+
+    #[test]
+    fn overflowing_array_lengths_are_rejected() {
+        let bytes = [0; 16];
+        let array = Reader::new(&bytes).array::<Vertex>(usize::max_value());
+        assert!(matches!(array, Err(FileError::Malformed)));
+    }
+}
@@ -0,0 +1,241 @@
+use gfx;
+
+use glsl::*;
+use polygon::{PolygonBuffer, PolygonId};
+use render::*;
+use states::texture_size;
+
+/// A fragment shader, supplied by the application, that colors the inside of draped polygons, for
+/// effects such as procedural stripes, tinting by attribute or animated flow.
+///
+/// The source is GLSL, without a `#version` line, that defines the function
+///
+/// ```glsl
+/// vec4 fill_color(vec4 color);
+/// ```
+///
+/// which returns the color of the polygon at the current fragment, given the color it would
+/// otherwise be filled with, such as a `Style`'s `fill_color` with its opacity applied, or the
+/// polygon's own color from `PolygonStates`. Hidden polygons never reach the function. The result
+/// is then highlighted and faded by `PolygonStates`, and blended into the render target, just as
+/// the built-in fill is. The function may `discard`, but must not write any outputs.
+///
+/// The source is compiled after the renderer's own fragment shader, as GLSL 1.50 or GLSL ES 3.00
+/// depending on the renderer's `ShaderLanguage`, and can use:
+///
+/// * `v_polygon_id`, a flat `uint`: the polygon's ID.
+/// * `fill_attributes()`, a function returning a `vec4`: the polygon's attributes chosen with
+///   `set_attributes`, in order, with NaN for those it doesn't have, or isn't a number. Check for
+///   them with `isnan`.
+/// * `u_color`, a `vec4`: the color passed to the renderer, which for a `DrapingScene` is the
+///   layer's `Style::fill_color` with the style's opacity applied. Unlike `color`, this doesn't
+///   include the polygon's own color.
+/// * `u_time`, a `float`: the time passed to the renderer, or 0 without one.
+/// * `u_mvp`, a `mat4`: the model-view-projection matrix passed to the renderer.
+/// * `u_fill_params`, a `mat4`: parameters of the application's choosing; see `set_params`.
+/// * `t_fill`, a `sampler2D`: a texture of the application's choosing, such as a pattern; see
+///   `set_texture`.
+/// * `gl_FragCoord`, as usual.
+///
+/// Polygons drawn with a fill shader are drawn one at a time, as with `PolygonStates`, so
+/// `v_polygon_id` and `fill_attributes()` always belong to the polygon the fragment lies in, even
+/// where the bounding boxes of neighbouring polygons overlap.
+///
+/// Use `fill_shader_programs` to check a fill shader without a GPU.
+#[derive(Clone, Debug)]
+pub struct FillShader<R: gfx::Resources> {
+    // One per `BlendMode`, in declaration order.
+    pub(crate) bounding_box_psos:
+        Vec<gfx::pso::PipelineState<R, z_fail_bounding_box_pipeline::Meta>>,
+    params: [[f32; 4]; 4],
+    pub(crate) texture: Option<SampledTexture<R>>,
+    // Texel 0 is all NaN, and polygon `i`'s attributes are in texel `i + 1`, so that polygons
+    // without attributes can share the first texel.
+    attributes: Option<gfx::handle::ShaderResourceView<R, [f32; 4]>>,
+    no_attributes: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    // What the renderer this was created for built its pipelines with.
+    pub(crate) variant: ShaderVariant,
+    pub(crate) antialiasing: Antialiasing,
+}
+
+// A texture and the sampler to read it with, as bound to `t_fill`.
+type SampledTexture<R> = (gfx::handle::ShaderResourceView<R, [f32; 4]>, gfx::handle::Sampler<R>);
+
+impl<R: gfx::Resources> FillShader<R> {
+    /// Compile `source` into pipelines for `renderer`, which this fill shader must only be used
+    /// with; `render_with_fill` panics otherwise.
+    ///
+    /// Returns an error naming the program `bounding_box-fill`, as `fill_shader_programs` does,
+    /// if `source` doesn't compile or link.
+    pub fn new<F: gfx::Factory<R>>(
+        factory: &mut F,
+        renderer: &DrapingRenderer<R>,
+        source: &str,
+    ) -> Result<FillShader<R>, DrapingError> {
+        let shaders = renderer.variant.compile(
+            factory,
+            "bounding_box-fill",
+            BOUNDING_BOX_VERT,
+            &fill_fragment(source),
+            &["CUSTOM_FILL"],
+        )?;
+
+        let bounding_box_psos = BlendMode::all()
+            .iter()
+            .map(|&blend| {
+                DrapingRenderer::bounding_box_pso(
+                    factory,
+                    &shaders,
+                    renderer.variant.depth,
+                    blend,
+                    renderer.antialiasing(),
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(FillShader {
+            bounding_box_psos: bounding_box_psos,
+            params: [[0.0; 4]; 4],
+            texture: None,
+            attributes: None,
+            no_attributes: Self::attribute_texture(factory, &[])?,
+            variant: renderer.variant,
+            antialiasing: renderer.antialiasing(),
+        })
+    }
+
+    /// The value of `u_fill_params`, as columns.
+    pub fn params(&self) -> [[f32; 4]; 4] {
+        self.params
+    }
+
+    /// Set the value of `u_fill_params`, as columns. It is initially all zeroes.
+    pub fn set_params(&mut self, params: [[f32; 4]; 4]) {
+        self.params = params;
+    }
+
+    /// Bind `texture` to `t_fill`, sampled with `sampler`. Without a texture, `t_fill` is a
+    /// single placeholder texel.
+    pub fn set_texture(
+        &mut self,
+        texture: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+        sampler: gfx::handle::Sampler<R>,
+    ) {
+        self.texture = Some((texture, sampler));
+    }
+
+    /// Stop binding a texture to `t_fill`.
+    pub fn clear_texture(&mut self) {
+        self.texture = None;
+    }
+
+    /// Make the numeric attributes `names` of the polygons in `buffer` available to the shader
+    /// through `fill_attributes()`, as its `x`, `y`, `z` and `w` in order.
+    ///
+    /// The attributes are copied, so call this again after changing them. Only polygons already
+    /// in `buffer` have their attributes copied, and this fill shader should only be used to draw
+    /// polygons from `buffer`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more than four `names`, or if the buffer has too many polygons to fit
+    /// in a texture, i.e. more than 64 million.
+    pub fn set_attributes<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        buffer: &PolygonBuffer,
+        names: &[&str],
+    ) -> Result<(), DrapingError> {
+        assert!(names.len() <= 4, "a fill shader can have at most four attributes");
+
+        let attributes: Vec<_> = (0..buffer.num_polygons)
+            .map(|id| {
+                let mut texel = [::std::f32::NAN; 4];
+                for (value, name) in texel.iter_mut().zip(names) {
+                    let attribute = buffer.attribute(PolygonId(id), name);
+                    if let Some(number) = attribute.and_then(|value| value.as_number()) {
+                        *value = number as f32;
+                    }
+                }
+
+                texel
+            })
+            .collect();
+
+        self.attributes = Some(Self::attribute_texture(factory, &attributes)?);
+        Ok(())
+    }
+
+    /// Stop passing attributes to the shader, so that `fill_attributes()` is NaN for every
+    /// polygon.
+    pub fn clear_attributes(&mut self) {
+        self.attributes = None;
+    }
+
+    pub(crate) fn attributes_view(&self) -> gfx::handle::ShaderResourceView<R, [f32; 4]> {
+        self.attributes
+            .as_ref()
+            .unwrap_or(&self.no_attributes)
+            .clone()
+    }
+
+    fn attribute_texture<F: gfx::Factory<R>>(
+        factory: &mut F,
+        attributes: &[[f32; 4]],
+    ) -> Result<gfx::handle::ShaderResourceView<R, [f32; 4]>, DrapingError> {
+        let nan = [::std::f32::NAN; 4];
+        let (width, height) = texture_size(attributes.len() + 1);
+        let texels: Vec<_> = (0..width * height)
+            .map(|index| {
+                let texel = index.checked_sub(1).and_then(|id| attributes.get(id));
+                let texel = texel.unwrap_or(&nan);
+                [
+                    texel[0].to_bits(),
+                    texel[1].to_bits(),
+                    texel[2].to_bits(),
+                    texel[3].to_bits(),
+                ]
+            })
+            .collect();
+
+        factory
+            .create_texture_immutable::<gfx::format::Rgba32F>(
+                gfx::texture::Kind::D2(width as u16, height as u16, gfx::texture::AaMode::Single),
+                gfx::texture::Mipmap::Provided,
+                &[&texels],
+            )
+            .map(|(_, view)| view)
+            .map_err(|error| DrapingError::Texture {
+                name: "t_fill_attributes".to_owned(),
+                error: error,
+            })
+    }
+}
+
+impl<R: gfx::Resources> DrapingRenderer<R> {
+    /// Render the polygons in `params` like `render`, with its states and time if it has them,
+    /// but fill them using `fill`. Without a time, `u_time` is 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fill` wasn't created for this renderer, or for one with the same shader
+    /// language, depth convention and antialiasing.
+    pub fn render_with_fill<C: gfx::CommandBuffer<R>>(
+        &self,
+        encoder: &mut gfx::Encoder<R, C>,
+        render_target: gfx::handle::RenderTargetView<R, gfx::format::Srgba8>,
+        depth_stencil_target: gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        blend: BlendMode,
+        fill: &FillShader<R>,
+        params: RenderParams<R>,
+    ) {
+        self.draw(
+            encoder,
+            render_target,
+            depth_stencil_target,
+            blend,
+            Some(fill),
+            params,
+        )
+    }
+}
@@ -0,0 +1,48 @@
+/// The six clipping planes of a view frustum.
+///
+/// Each plane is stored as `[a, b, c, d]`, where a point `(x, y, z)` is on the inner side of the
+/// plane if `a * x + b * y + c * z + d >= 0`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Frustum {
+    planes: [[f32; 4]; 6],
+}
+
+impl Frustum {
+    /// Extract the frustum of a column-major model-view-projection matrix, as passed to
+    /// `DrapingRenderer::render`.
+    pub fn from_mvp(mvp: [[f32; 4]; 4]) -> Frustum {
+        let row = |i: usize| [mvp[0][i], mvp[1][i], mvp[2][i], mvp[3][i]];
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let add = |a: [f32; 4], b: [f32; 4]| [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]];
+        let sub = |a: [f32; 4], b: [f32; 4]| [a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]];
+
+        Frustum {
+            planes: [
+                add(w, x),
+                sub(w, x),
+                add(w, y),
+                sub(w, y),
+                add(w, z),
+                sub(w, z),
+            ],
+        }
+    }
+
+    /// Whether any part of the axis-aligned box between `min` and `max` may be inside the
+    /// frustum.
+    ///
+    /// This test is conservative: some boxes near the corners of the frustum are reported as
+    /// visible even though they are not, but a visible box is never reported as invisible.
+    pub fn intersects_box(&self, min: [f32; 3], max: [f32; 3]) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner of the box furthest along the plane's normal. If even that corner is
+            // outside the plane, the whole box is.
+            let x = if plane[0] > 0.0 { max[0] } else { min[0] };
+            let y = if plane[1] > 0.0 { max[1] } else { min[1] };
+            let z = if plane[2] > 0.0 { max[2] } else { min[2] };
+
+            plane[0] * x + plane[1] * y + plane[2] * z + plane[3] >= 0.0
+        })
+    }
+}
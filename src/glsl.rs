@@ -0,0 +1,377 @@
+use gfx;
+use gfx::traits::FactoryExt;
+
+use deferred::MAX_TARGETS;
+use render::{DepthConvention, DrapingError};
+
+pub(crate) const POLYHEDRON_VERT: &[u8] = include_bytes!("shaders/polyhedron.vert");
+pub(crate) const POLYHEDRON_FRAG: &[u8] = include_bytes!("shaders/polyhedron.frag");
+pub(crate) const BOUNDING_BOX_VERT: &[u8] = include_bytes!("shaders/bounding_box.vert");
+pub(crate) const BOUNDING_BOX_FRAG: &[u8] = include_bytes!("shaders/bounding_box.frag");
+
+/// A `FillShader` that leaves the polygon's color unchanged.
+const PASS_THROUGH_FILL: &str = "vec4 fill_color(vec4 color) {\n    return color;\n}\n";
+
+/// The dialect of GLSL a `DrapingRenderer`'s shaders are compiled as.
+///
+/// The shaders are written once, as GLSL 1.50, and translated to GLSL ES 3.00 by replacing their
+/// `#version` line and adding default precisions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    /// `#version 150 core`, for desktop OpenGL 3.2 and later.
+    Glsl150,
+    /// `#version 300 es`, for OpenGL ES 3.0 and WebGL 2.
+    GlslEs300,
+}
+
+impl ShaderLanguage {
+    /// Every language, in the order `DrapingRenderer::new` tries them.
+    pub(crate) fn all() -> [ShaderLanguage; 2] {
+        [ShaderLanguage::Glsl150, ShaderLanguage::GlslEs300]
+    }
+
+    fn header(&self) -> &'static str {
+        match *self {
+            ShaderLanguage::Glsl150 => "#version 150 core\n",
+            ShaderLanguage::GlslEs300 => "#version 300 es\n",
+        }
+    }
+
+    fn precisions(&self) -> &'static str {
+        match *self {
+            ShaderLanguage::Glsl150 => "",
+            ShaderLanguage::GlslEs300 => {
+                "precision highp float;\nprecision highp int;\nprecision highp sampler2D;\n"
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            ShaderLanguage::Glsl150 => "glsl150",
+            ShaderLanguage::GlslEs300 => "gles300",
+        }
+    }
+}
+
+/// The language and depth convention shaders are built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ShaderVariant {
+    pub language: ShaderLanguage,
+    pub depth: DepthConvention,
+}
+
+impl ShaderVariant {
+    /// Choose the first language whose shaders `factory` accepts, so that the same code runs on
+    /// desktop OpenGL and on OpenGL ES or WebGL.
+    ///
+    /// If no language is accepted, GLSL 1.50 is chosen, so that the errors building the
+    /// renderer's pipelines are about the desktop shaders.
+    pub fn detect<R: gfx::Resources, F: gfx::Factory<R>>(
+        factory: &mut F,
+        depth: DepthConvention,
+    ) -> ShaderVariant {
+        let language = ShaderLanguage::all()
+            .iter()
+            .cloned()
+            .find(|&language| {
+                let variant = ShaderVariant {
+                    language: language,
+                    depth: depth,
+                };
+
+                factory
+                    .create_shader_set(
+                        &variant.source(POLYHEDRON_VERT, &[]),
+                        &variant.source(POLYHEDRON_FRAG, &[]),
+                    )
+                    .is_ok()
+            })
+            .unwrap_or(ShaderLanguage::Glsl150);
+
+        ShaderVariant {
+            language: language,
+            depth: depth,
+        }
+    }
+
+    /// `source`, one of the shaders in `src/shaders`, translated for this variant, with a
+    /// `#define` for each of `defines`.
+    pub fn source(&self, source: &[u8], defines: &[&str]) -> Vec<u8> {
+        let version_end = source.iter().position(|&byte| byte == b'\n').unwrap() + 1;
+
+        let mut result = self.language.header().to_owned();
+        if self.depth == DepthConvention::Reversed {
+            result.push_str("#define REVERSED_Z\n");
+        }
+        for define in defines {
+            result.push_str(&format!("#define {}\n", define));
+        }
+        result.push_str(self.language.precisions());
+
+        let mut result = result.into_bytes();
+        result.extend(&source[version_end..]);
+        result
+    }
+
+    /// Compile `vertex` and `fragment`, with `defines` in the fragment shader, as the program
+    /// `shader_programs` names `program`.
+    pub fn compile<R: gfx::Resources, F: gfx::Factory<R>>(
+        &self,
+        factory: &mut F,
+        program: &str,
+        vertex: &[u8],
+        fragment: &[u8],
+        defines: &[&str],
+    ) -> Result<Shaders<R>, DrapingError> {
+        let name = format!("{}-{}", program, self.name());
+        let set = factory
+            .create_shader_set(&self.source(vertex, &[]), &self.source(fragment, defines))
+            .map_err(|error| program_error(&name, error))?;
+
+        Ok(Shaders {
+            name: name,
+            set: set,
+        })
+    }
+
+    fn all() -> Vec<ShaderVariant> {
+        let mut variants = Vec::new();
+        for &language in &ShaderLanguage::all() {
+            for &depth in &[DepthConvention::Standard, DepthConvention::Reversed] {
+                variants.push(ShaderVariant {
+                    language: language,
+                    depth: depth,
+                });
+            }
+        }
+
+        variants
+    }
+
+    fn program(
+        &self,
+        name: &str,
+        vertex: &[u8],
+        fragment: &[u8],
+        defines: &[&str],
+    ) -> ShaderProgram {
+        ShaderProgram {
+            name: format!("{}-{}", name, self.name()),
+            vertex: String::from_utf8(self.source(vertex, &[])).unwrap(),
+            fragment: String::from_utf8(self.source(fragment, defines)).unwrap(),
+        }
+    }
+
+    fn fill_program(&self, source: &str) -> ShaderProgram {
+        self.program(
+            "bounding_box-fill",
+            BOUNDING_BOX_VERT,
+            &fill_fragment(source),
+            &["CUSTOM_FILL"],
+        )
+    }
+
+    fn name(&self) -> String {
+        let depth = match self.depth {
+            DepthConvention::Standard => "standard",
+            DepthConvention::Reversed => "reversed",
+        };
+
+        format!("{}-{}", self.language.name(), depth)
+    }
+}
+
+/// A compiled vertex and fragment shader, named for errors as in `shader_programs`.
+#[derive(Clone, Debug)]
+pub(crate) struct Shaders<R: gfx::Resources> {
+    name: String,
+    set: gfx::ShaderSet<R>,
+}
+
+impl<R: gfx::Resources> Shaders<R> {
+    /// Create a pipeline drawing triangle lists with these shaders.
+    pub fn pipeline<F: gfx::Factory<R>, I: gfx::pso::PipelineInit>(
+        &self,
+        factory: &mut F,
+        rasterizer: gfx::state::Rasterizer,
+        init: I,
+    ) -> Result<gfx::pso::PipelineState<R, I::Meta>, DrapingError> {
+        factory
+            .create_pipeline_state(&self.set, gfx::Primitive::TriangleList, rasterizer, init)
+            .map_err(|error| match error {
+                gfx::PipelineStateError::Program(error) => program_error(&self.name, error),
+                error => DrapingError::Pipeline {
+                    name: self.name.clone(),
+                    error: error,
+                },
+            })
+    }
+}
+
+fn program_error(name: &str, error: gfx::shade::ProgramError) -> DrapingError {
+    match error {
+        gfx::shade::ProgramError::Vertex(error) => DrapingError::Shader {
+            name: format!("{}.vert", name),
+            error: error,
+        },
+        gfx::shade::ProgramError::Pixel(error) => DrapingError::Shader {
+            name: format!("{}.frag", name),
+            error: error,
+        },
+        gfx::shade::ProgramError::Link(error) => DrapingError::Link {
+            name: name.to_owned(),
+            error: error,
+        },
+        // The renderer has no other shader stages.
+        error => DrapingError::Pipeline {
+            name: name.to_owned(),
+            error: gfx::PipelineStateError::Program(error),
+        },
+    }
+}
+
+/// A vertex and fragment shader pair that a `DrapingRenderer` may build, as returned by
+/// `shader_programs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderProgram {
+    /// A name for the program, unique among those returned by `shader_programs`, and usable as a
+    /// file name.
+    pub name: String,
+    /// The vertex shader's source.
+    pub vertex: String,
+    /// The fragment shader's source.
+    pub fragment: String,
+}
+
+/// Every shader program a `DrapingRenderer` can build, in every `ShaderLanguage` and
+/// `DepthConvention`.
+///
+/// These are meant for checking the shaders offline with a compiler such as `glslangValidator`,
+/// as `gfx-draping check-shaders` does. The programs for `FillShader`s are included with a fill
+/// shader that returns its color unchanged.
+pub fn shader_programs() -> Vec<ShaderProgram> {
+    let mut programs = Vec::new();
+
+    for variant in ShaderVariant::all() {
+        programs.push(variant.program("polyhedron", POLYHEDRON_VERT, POLYHEDRON_FRAG, &[]));
+        programs.push(variant.program("bounding_box", BOUNDING_BOX_VERT, BOUNDING_BOX_FRAG, &[]));
+        programs.push(variant.program(
+            "bounding_box-mask",
+            BOUNDING_BOX_VERT,
+            BOUNDING_BOX_FRAG,
+            &["MASK"],
+        ));
+
+        for count in 1..MAX_TARGETS + 1 {
+            programs.push(variant.program(
+                &format!("bounding_box-targets{}", count),
+                BOUNDING_BOX_VERT,
+                BOUNDING_BOX_FRAG,
+                &[&format!("TARGET_COUNT {}", count)],
+            ));
+        }
+
+        programs.push(variant.fill_program(PASS_THROUGH_FILL));
+    }
+
+    programs
+}
+
+/// The programs a `FillShader` with `source` would build, in every `ShaderLanguage` and
+/// `DepthConvention`, for checking it offline as with `shader_programs`.
+pub fn fill_shader_programs(source: &str) -> Vec<ShaderProgram> {
+    ShaderVariant::all()
+        .iter()
+        .map(|variant| variant.fill_program(source))
+        .collect()
+}
+
+/// The fragment shader a `FillShader` with `source` is built from, before it is translated.
+pub(crate) fn fill_fragment(source: &str) -> Vec<u8> {
+    let mut fragment = BOUNDING_BOX_FRAG.to_vec();
+    fragment.push(b'\n');
+    fragment.extend(source.as_bytes());
+    fragment
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process::{self, Command};
+
+    use super::*;
+
+    /// Compile and link `program` with `glslangValidator`, writing its shaders to a directory
+    /// named after `test`, or return `None` if `glslangValidator` isn't installed.
+    fn glslang(test: &str, program: &ShaderProgram) -> Option<Result<(), String>> {
+        let dir = env::temp_dir().join(format!("gfx-draping-{}-{}", test, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let vertex = dir.join(format!("{}.vert", program.name));
+        let fragment = dir.join(format!("{}.frag", program.name));
+        fs::write(&vertex, &program.vertex).unwrap();
+        fs::write(&fragment, &program.fragment).unwrap();
+
+        let output = Command::new("glslangValidator")
+            .arg("-l")
+            .arg(&vertex)
+            .arg(&fragment)
+            .output();
+        let output = match output {
+            Ok(output) => output,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return None,
+            Err(error) => panic!("couldn't run glslangValidator: {}", error),
+        };
+
+        if output.status.success() {
+            Some(Ok(()))
+        } else {
+            Some(Err(String::from_utf8_lossy(&output.stdout).into_owned()))
+        }
+    }
+
+    /// Check every program in `programs` with `glslangValidator`, returning the results, or an
+    /// empty list if it isn't installed.
+    fn check(test: &str, programs: &[ShaderProgram]) -> Vec<Result<(), String>> {
+        let mut results = Vec::new();
+        for program in programs {
+            match glslang(test, program) {
+                Some(result) => results.push(result),
+                None => {
+                    eprintln!("glslangValidator isn't installed; skipping {}", test);
+                    return Vec::new();
+                }
+            }
+        }
+
+        results
+    }
+
+    #[test]
+    fn every_shader_program_compiles() {
+        let programs = shader_programs();
+        for (program, result) in programs.iter().zip(check("programs", &programs)) {
+            assert!(result.is_ok(), "{}: {}", program.name, result.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn fill_shaders_are_compiled_with_the_renderers_declarations() {
+        let source = "vec4 fill_color(vec4 color) {\n\
+                          if (!isnan(fill_attributes().x) && v_polygon_id > 0u) {\n\
+                              return u_color * texture(t_fill, gl_FragCoord.xy);\n\
+                          }\n\
+                          return color;\n\
+                      }\n";
+        for result in check("fill", &fill_shader_programs(source)) {
+            assert_eq!(result, Ok(()));
+        }
+
+        let wrong_type = "vec4 fill_color(vec4 color) { return color.x; }\n";
+        for result in check("wrong-type", &fill_shader_programs(wrong_type)) {
+            assert!(result.is_err());
+        }
+    }
+}
@@ -0,0 +1,106 @@
+/// A source of terrain heights, used to fit each polygon's shadow volume tightly around the
+/// terrain beneath it.
+///
+/// Heights are in the same units as the z-axis of a `PolygonBuffer`, *before* your `mvp` is
+/// applied. A polygon added with `PolygonBuffer::add` spans z-values between 0 and 1, so if your
+/// `mvp` stretches that range to `min_z..max_z`, an elevation `e` should be reported as
+/// `(e - min_z) / (max_z - min_z)`.
+///
+/// This trait is implemented for `Heightfield`, and for any closure taking the same arguments as
+/// `height_range`.
+pub trait HeightQuery {
+    /// The lowest and highest terrain height, as `(min_z, max_z)`, anywhere within `bounds`.
+    ///
+    /// `bounds` is `[(min_x, max_x), (min_y, max_y)]`, just like the bounds passed to
+    /// `Polygon::new`. It is fine to over-estimate the range; under-estimating it will cause
+    /// parts of the polygon to disappear.
+    fn height_range(&self, bounds: [(f32, f32); 2]) -> (f32, f32);
+}
+
+impl<F: Fn([(f32, f32); 2]) -> (f32, f32)> HeightQuery for F {
+    fn height_range(&self, bounds: [(f32, f32); 2]) -> (f32, f32) {
+        self(bounds)
+    }
+}
+
+/// A regular grid of terrain heights.
+///
+/// The terrain is assumed to be interpolated linearly between samples, so the height range
+/// within some bounds is found by looking at every sample in or immediately around those bounds.
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    origin: (f32, f32),
+    spacing: (f32, f32),
+    width: usize,
+    heights: Vec<f32>,
+}
+
+impl Heightfield {
+    /// Construct a heightfield from a row-major grid of samples.
+    ///
+    /// The sample at column `i` and row `j` is `heights[i + j * width]`, and is located at
+    /// `(origin.0 + i * spacing.0, origin.1 + j * spacing.1)`. See `HeightQuery` for what units
+    /// the heights should be in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `heights` is empty or its length is not a multiple of `width`.
+    pub fn new(
+        origin: (f32, f32),
+        spacing: (f32, f32),
+        width: usize,
+        heights: Vec<f32>,
+    ) -> Heightfield {
+        assert!(
+            width > 0 && !heights.is_empty() && heights.chunks_exact(width).remainder().is_empty()
+        );
+
+        Heightfield {
+            origin: origin,
+            spacing: spacing,
+            width: width,
+            heights: heights,
+        }
+    }
+
+    fn rows(&self) -> usize {
+        self.heights.len() / self.width
+    }
+
+    /// The range of sample indices along one axis that surround `min..max`.
+    fn sample_range(min: f32, max: f32, origin: f32, spacing: f32, len: usize) -> (usize, usize) {
+        let clamp = |i: f32| i.max(0.0).min((len - 1) as f32) as usize;
+
+        // With a negative spacing, `min` is at a higher index than `max`.
+        let (a, b) = ((min - origin) / spacing, (max - origin) / spacing);
+        (clamp(a.min(b).floor()), clamp(a.max(b).ceil()))
+    }
+}
+
+impl HeightQuery for Heightfield {
+    fn height_range(&self, bounds: [(f32, f32); 2]) -> (f32, f32) {
+        let (min_i, max_i) = Self::sample_range(
+            bounds[0].0,
+            bounds[0].1,
+            self.origin.0,
+            self.spacing.0,
+            self.width,
+        );
+        let (min_j, max_j) = Self::sample_range(
+            bounds[1].0,
+            bounds[1].1,
+            self.origin.1,
+            self.spacing.1,
+            self.rows(),
+        );
+
+        let mut range = (::std::f32::INFINITY, ::std::f32::NEG_INFINITY);
+        for j in min_j..max_j + 1 {
+            for height in &self.heights[min_i + j * self.width..max_i + j * self.width + 1] {
+                range = (range.0.min(*height), range.1.max(*height));
+            }
+        }
+
+        range
+    }
+}
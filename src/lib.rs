@@ -12,7 +12,8 @@
 //! extern crate geo;
 //! extern crate gfx_draping;
 //!
-//! use gfx_draping::{DrapingRenderer, PolygonBuffer, PolygonBufferIndices};
+//! use gfx_draping::{BlendMode, DepthConvention, DrapingRenderer, PolygonBuffer,
+//!                   PolygonBufferIndices, RenderParams};
 //!
 //! // Let's say you're using `geo` (a Rust GIS crate) to construct polygons.
 //! let polygons: Vec<geo::Polygon> = a_vec_of_polygons();
@@ -24,7 +25,7 @@
 //!     indices.extend(buffer.add(polygon.into()));
 //! }
 //!
-//! let renderer = DrapingRenderer::new();
+//! let renderer = DrapingRenderer::new(&mut window.factory, DepthConvention::Standard);
 //! let renderable_buffer = buffer.as_renderable(&mut window.factory);
 //! let renderable_indices = indices.as_renderable(&mut window.factory);
 //!
@@ -38,25 +39,114 @@
 //!         window.encoder,
 //!         window.output_color,
 //!         window.output_stencil,
-//!         // See docs for `DrapingRenderer::render` for a caveat about what `mvp` should be.
-//!         your_scaled_mvp(),
-//!         // R - G - B - A
-//!         [1.0, 0.0, 1.0, 0.5],
-//!         &renderable_buffer,
-//!         &renderable_indices,
+//!         BlendMode::Alpha,
+//!         RenderParams::new(
+//!             // See docs for `DrapingRenderer::render` for a caveat about what `mvp` should be.
+//!             your_scaled_mvp(),
+//!             // R - G - B - A
+//!             [1.0, 0.0, 1.0, 0.5],
+//!             &renderable_buffer,
+//!             &renderable_indices,
+//!         ),
 //!     );
 //!
 //!     // Now you can clear / clean-up as you do usually.
 //! }
 //! ```
+//!
+//! If you draw several sets of polygons, each with their own color, a `DrapingScene` can keep
+//! track of them for you as `Layer`s. Each layer has a `Style` (fill color, outline color, blend
+//! mode and opacity), can be shown or hidden, and has an order relative to the other layers. The
+//! whole scene is drawn with one call to `DrapingScene::render`.
+//!
+//! Rather than splitting polygons into layers by color yourself, you can attach attributes to them
+//! with `PolygonBuffer::set_attribute` and color them with `ColorRules`.
+//!
+//! To hide, highlight or fade individual polygons from frame to frame, such as on hover, use
+//! `PolygonStates` rather than rebuilding your `PolygonBufferIndices`.
+//!
+//! Polygon edges are hard-aliased by default; see `Antialiasing` for ways of smoothing them.
+//!
+//! Each polygon is colored by drawing a `CoverVolume` over it, which is chosen to keep the pixels
+//! filled close to the polygon's own; see `Polygon::cover_volume` to choose one yourself.
+//! Long or concave polygons fill fewer pixels when their caps are triangulated by
+//! `CapTriangulation::EarClipping`, at some cost when adding them to a `PolygonBuffer`.
+//!
+//! To post-process draped polygons, such as for glows or selection halos, render their coverage
+//! into a `MaskTexture` with `DrapingRenderer::render_mask`.
+//!
+//! Deferred renderers can draw polygons into their G-buffer with
+//! `DrapingRenderer::with_deferred_targets` and `render_deferred`.
+//!
+//! The shaders are compiled as GLSL 1.50 on desktop OpenGL, and as GLSL ES 3.00 on OpenGL ES and
+//! WebGL 2. Every variant can be checked without a GPU; see `shader_programs`. To fall back to
+//! another way of drawing polygons where they can't be compiled, create the renderer with
+//! `DrapingRenderer::try_new`.
+//!
+//! For effects inside polygons, such as stripes or animated flow, supply a fragment shader of
+//! your own as a `FillShader`.
+//!
+//! The order of the draws and the pipeline state of each are described independently of gfx by
+//! `Pass` and `DrawCommand`. `PolygonBufferIndices::draw_commands` passes them to any `DrawSink`,
+//! such as a `DrawRecorder` in tests, or another renderer reusing the stencil sequencing.
+//! Polygons drawn with `PolygonStates` or a `FillShader` are drawn one at a time instead, as
+//! `RenderablePolygonIndices::draw_commands_per_polygon` describes, so that each is drawn only
+//! where it lies even if its bounding box overlaps another's.
+//!
+//! # Features
+//!
+//! * `mmap`: adds `MappedBufferFile`, for memory-mapping prebuilt `BufferFile`s from disk.
+//! * `serde`: implements `Serialize` and `Deserialize` for `Polygon`, `CoverVolume`,
+//!   `PolygonBuffer` and `PolygonBufferIndices`, so that prebuilt buffers can be cached in your
+//!   own asset formats. The serialized format is versioned: data written by a version of this
+//!   crate with a different format fails to deserialize, and should be rebuilt from its polygons.
 
 extern crate geo;
 #[macro_use]
 extern crate gfx;
+#[cfg(test)]
+extern crate gfx_core;
+#[cfg(feature = "mmap")]
+extern crate memmap;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
+mod commands;
+mod deferred;
+mod file;
+mod fill;
+mod frustum;
+mod glsl;
+mod height;
+mod mask;
 mod polygon;
 mod render;
+mod rules;
+mod scene;
+mod states;
+mod surface;
+mod triangulate;
 mod vertex;
 
-pub use polygon::{Polygon, PolygonBuffer, PolygonBufferIndices};
-pub use render::{DrapingRenderer, RenderablePolygonBuffer, RenderablePolygonIndices};
+pub use commands::{z_fail_commands, z_fail_commands_per_polygon, Comparison, CullFace,
+                   DrawCommand, DrawRecorder, DrawSink, DrawState, Geometry, Pass, StencilFace,
+                   StencilOp, StencilState};
+pub use deferred::{DeferredOutput, DeferredTarget};
+#[cfg(feature = "mmap")]
+pub use file::MappedBufferFile;
+pub use file::{BufferFile, FileError};
+pub use fill::FillShader;
+pub use glsl::{fill_shader_programs, shader_programs, ShaderLanguage, ShaderProgram};
+pub use height::{HeightQuery, Heightfield};
+pub use mask::{MaskOutput, MaskTargetError, MaskTexture};
+pub use polygon::{AttributeValue, CoverVolume, Polygon, PolygonBuffer, PolygonBufferIndices,
+                  PolygonId};
+pub use render::{Antialiasing, BlendMode, DepthConvention, DrapingError, DrapingRenderer,
+                 IndexFormat, IndexOverflowError, RenderParams, RenderablePolygonBuffer,
+                 RenderablePolygonIndices};
+pub use rules::{ColorRule, ColorRules, Filter};
+pub use scene::{DrapingScene, Layer, LayerId, Style};
+pub use states::PolygonStates;
+pub use surface::{Ellipsoid, Globe, Surface, UpAxis};
+pub use triangulate::CapTriangulation;
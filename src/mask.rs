@@ -0,0 +1,413 @@
+use std::error::Error;
+use std::fmt;
+
+use gfx;
+use gfx::format::{R8_G8_B8_A8, Rgba8};
+use gfx::texture::{AaMode, Dimensions};
+
+use commands::Pass;
+use glsl::*;
+use render::*;
+use vertex::Vertex;
+
+gfx_pipeline!(z_fail_mask_polyhedron_pipeline {
+    mvp: gfx::Global<[[f32; 4]; 4]> = "u_mvp",
+    states: gfx::TextureSampler<[f32; 4]> = "t_states",
+    use_states: gfx::Global<i32> = "u_use_states",
+    time: gfx::Global<f32> = "u_time",
+    filter_by_time: gfx::Global<i32> = "u_filter_by_time",
+    validity: gfx::TextureSampler<[f32; 4]> = "t_validity",
+    vertex_buffer: gfx::VertexBuffer<Vertex> = (),
+    out_color: gfx::BlendTarget<Rgba8> = (
+        "o_color",
+        gfx::state::ColorMask::empty(),
+        gfx::preset::blend::REPLACE,
+    ),
+    out_depth_stencil: gfx::DepthStencilTarget<gfx::format::DepthStencil> = (
+        gfx::preset::depth::LESS_EQUAL_TEST,
+        polyhedron_stencil(),
+    ),
+});
+
+gfx_pipeline!(z_fail_mask_bounding_box_pipeline {
+    out_mask: gfx::BlendTarget<Rgba8> = (
+        "o_mask",
+        gfx::state::ColorMask::all(),
+        gfx::preset::blend::REPLACE,
+    ),
+    mvp: gfx::Global<[[f32; 4]; 4]> = "u_mvp",
+    color: gfx::Global<[f32; 4]> = "u_color",
+    highlight_color: gfx::Global<[f32; 4]> = "u_highlight_color",
+    highlight_pulse: gfx::Global<f32> = "u_highlight_pulse",
+    states: gfx::TextureSampler<[f32; 4]> = "t_states",
+    colors: gfx::TextureSampler<[f32; 4]> = "t_colors",
+    fades: gfx::TextureSampler<[f32; 4]> = "t_fades",
+    use_states: gfx::Global<i32> = "u_use_states",
+    time: gfx::Global<f32> = "u_time",
+    filter_by_time: gfx::Global<i32> = "u_filter_by_time",
+    validity: gfx::TextureSampler<[f32; 4]> = "t_validity",
+    mask_output: gfx::Global<i32> = "u_mask_output",
+    vertex_buffer: gfx::VertexBuffer<Vertex> = (),
+    out_depth_stencil: gfx::DepthStencilTarget<gfx::format::DepthStencil> = (
+        gfx::preset::depth::PASS_TEST,
+        bounding_box_stencil(),
+    ),
+});
+
+/// What `DrapingRenderer::render_mask` writes for each pixel a polygon covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaskOutput {
+    /// The polygon's alpha, after any `PolygonStates`, in every channel. This is 1 wherever an
+    /// opaque polygon covers the terrain, and is the usual input to a blur or edge detection.
+    Coverage,
+    /// The polygon's ID plus one, as a little-endian 32-bit integer spread over the red, green,
+    /// blue and alpha channels. Pixels no polygon covers keep their cleared value, so clear the
+    /// mask to zero to tell them apart from polygon 0.
+    PolygonId,
+    /// The polygon's color, after any `PolygonStates`, alpha-blended over the mask.
+    Color,
+}
+
+impl MaskOutput {
+    fn all() -> [MaskOutput; 3] {
+        [MaskOutput::Coverage, MaskOutput::PolygonId, MaskOutput::Color]
+    }
+
+    fn blend(&self) -> gfx::state::Blend {
+        match *self {
+            MaskOutput::Coverage | MaskOutput::PolygonId => gfx::preset::blend::REPLACE,
+            MaskOutput::Color => gfx::preset::blend::ALPHA,
+        }
+    }
+}
+
+/// An offscreen texture for `DrapingRenderer::render_mask` to draw into, which later passes can
+/// sample.
+///
+/// The mask is stored as linear RGBA with 8 bits per channel, so that polygon IDs survive
+/// exactly.
+#[derive(Clone, Debug)]
+pub struct MaskTexture<R: gfx::Resources> {
+    texture: gfx::handle::Texture<R, R8_G8_B8_A8>,
+    render_target: gfx::handle::RenderTargetView<R, Rgba8>,
+    shader_resource: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+}
+
+impl<R: gfx::Resources> MaskTexture<R> {
+    /// Create a single-sampled mask `width` by `height` pixels in size.
+    ///
+    /// The mask is drawn using a depth-stencil target holding the terrain's depth, which must be
+    /// the same size and also single-sampled; `render_mask` returns an error otherwise. The
+    /// scene's own depth-stencil target can only be reused if it meets both requirements, so a
+    /// mask at a lower resolution than the scene, or for a scene rendered with multisampled
+    /// `Antialiasing`, needs a depth-stencil target of its own, with the terrain drawn into it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the texture can't be created; see `try_new`.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, width: u16, height: u16) -> MaskTexture<R> {
+        Self::try_new(factory, width, height).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `new`, but returns an error rather than panicking if the texture can't be created.
+    pub fn try_new<F: gfx::Factory<R>>(
+        factory: &mut F,
+        width: u16,
+        height: u16,
+    ) -> Result<MaskTexture<R>, DrapingError> {
+        let (texture, shader_resource, render_target) = factory
+            .create_render_target::<Rgba8>(width, height)
+            .map_err(|error| DrapingError::Texture {
+                name: "mask".to_owned(),
+                error: error,
+            })?;
+
+        Ok(MaskTexture {
+            texture: texture,
+            render_target: render_target,
+            shader_resource: shader_resource,
+        })
+    }
+
+    /// The width and height of the mask, in pixels.
+    pub fn size(&self) -> (u16, u16) {
+        let (width, height, _, _) = self.texture.get_info().kind.get_dimensions();
+        (width, height)
+    }
+
+    /// Set every pixel of the mask to zero.
+    pub fn clear<C: gfx::CommandBuffer<R>>(&self, encoder: &mut gfx::Encoder<R, C>) {
+        encoder.clear(&self.render_target, [0.0; 4]);
+    }
+
+    /// The view to pass to `DrapingRenderer::render_mask`.
+    pub fn render_target(&self) -> gfx::handle::RenderTargetView<R, Rgba8> {
+        self.render_target.clone()
+    }
+
+    /// The view to sample the mask through in later passes.
+    pub fn shader_resource(&self) -> gfx::handle::ShaderResourceView<R, [f32; 4]> {
+        self.shader_resource.clone()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MaskPipelines<R: gfx::Resources> {
+    polyhedron_pso: gfx::pso::PipelineState<R, z_fail_mask_polyhedron_pipeline::Meta>,
+    // One per `MaskOutput`, in declaration order.
+    bounding_box_psos: Vec<gfx::pso::PipelineState<R, z_fail_mask_bounding_box_pipeline::Meta>>,
+}
+
+impl<R: gfx::Resources> MaskPipelines<R> {
+    pub(crate) fn new<F: gfx::Factory<R>>(
+        factory: &mut F,
+        variant: ShaderVariant,
+        polyhedron_shaders: &Shaders<R>,
+    ) -> Result<MaskPipelines<R>, DrapingError> {
+        // The mask is written by a variant of the bounding box fragment shader with a single
+        // `o_mask` output.
+        let bounding_box_shaders = variant.compile(
+            factory,
+            "bounding_box-mask",
+            BOUNDING_BOX_VERT,
+            BOUNDING_BOX_FRAG,
+            &["MASK"],
+        )?;
+        let rasterizer = rasterizer(Pass::Cover, variant.depth);
+
+        let bounding_box_psos = MaskOutput::all()
+            .iter()
+            .map(|output| {
+                let init = z_fail_mask_bounding_box_pipeline::Init {
+                    out_mask: ("o_mask", gfx::state::ColorMask::all(), output.blend()),
+                    ..z_fail_mask_bounding_box_pipeline::new()
+                };
+
+                bounding_box_shaders.pipeline(factory, rasterizer, init)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(MaskPipelines {
+            polyhedron_pso: Self::polyhedron_pso(factory, polyhedron_shaders, variant, None)?,
+            bounding_box_psos: bounding_box_psos,
+        })
+    }
+
+    pub(crate) fn set_depth_bias<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        polyhedron_shaders: &Shaders<R>,
+        variant: ShaderVariant,
+        depth_bias: Option<gfx::state::Offset>,
+    ) -> Result<(), DrapingError> {
+        self.polyhedron_pso =
+            Self::polyhedron_pso(factory, polyhedron_shaders, variant, depth_bias)?;
+        Ok(())
+    }
+
+    fn polyhedron_pso<F: gfx::Factory<R>>(
+        factory: &mut F,
+        shaders: &Shaders<R>,
+        variant: ShaderVariant,
+        depth_bias: Option<gfx::state::Offset>,
+    ) -> Result<
+        gfx::pso::PipelineState<R, z_fail_mask_polyhedron_pipeline::Meta>,
+        DrapingError,
+    > {
+        let rasterizer = gfx::state::Rasterizer {
+            offset: depth_bias,
+            ..rasterizer(Pass::Polyhedron, variant.depth)
+        };
+
+        shaders.pipeline(
+            factory,
+            rasterizer,
+            z_fail_mask_polyhedron_pipeline::Init {
+                out_depth_stencil: (variant.depth.polyhedron_test(), polyhedron_stencil()),
+                ..z_fail_mask_polyhedron_pipeline::new()
+            },
+        )
+    }
+}
+
+impl<R: gfx::Resources> DrapingRenderer<R> {
+    /// Render the coverage, IDs or colors of polygons into an offscreen mask rather than the
+    /// scene, for post-processing such as glows and selection halos.
+    ///
+    /// `mask` is usually `MaskTexture::render_target`, but can be any view of a single-sampled,
+    /// linear RGBA target, cleared beforehand. `depth_stencil_target` should hold the terrain's
+    /// depth, as for `render`; the scene's own depth-stencil target can be reused if it is the
+    /// same size as `mask` and single-sampled, since drawing the mask leaves its stencil buffer
+    /// zero. Returns an error, without drawing anything, if either target is multisampled or
+    /// their sizes differ.
+    ///
+    /// The polygons in `params` are drawn as by `render`, with its states and time if
+    /// it has them. Hidden polygons are left out of the mask whatever `output` is. Polygon IDs,
+    /// and any output drawn with states, are drawn one polygon at a time, so that neighbours whose
+    /// bounding boxes overlap don't write each other's IDs or colors.
+    pub fn render_mask<C: gfx::CommandBuffer<R>>(
+        &self,
+        encoder: &mut gfx::Encoder<R, C>,
+        mask: gfx::handle::RenderTargetView<R, Rgba8>,
+        depth_stencil_target: gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        output: MaskOutput,
+        params: RenderParams<R>,
+    ) -> Result<(), MaskTargetError> {
+        check_targets(mask.get_dimensions(), depth_stencil_target.get_dimensions())?;
+
+        let RenderParams {
+            mvp,
+            color,
+            buffer,
+            indices,
+            states,
+            time,
+        } = params;
+        let state_params = self.state_params(states, color);
+        let use_states = states.is_some() as i32;
+        let filter_by_time = time.is_some() as i32;
+        let time = time.unwrap_or(0.0);
+
+        let polyhedron_data = z_fail_mask_polyhedron_pipeline::Data {
+            mvp: mvp,
+            states: (state_params.states.clone(), self.sampler.clone()),
+            use_states: use_states,
+            time: time,
+            filter_by_time: filter_by_time,
+            validity: (buffer.validity_view.clone(), self.sampler.clone()),
+            vertex_buffer: buffer.polyhedron_vertex_buffer.clone(),
+            out_color: mask.clone(),
+            out_depth_stencil: (depth_stencil_target.clone(), (0, 0)),
+        };
+
+        let bounding_box_data = z_fail_mask_bounding_box_pipeline::Data {
+            out_mask: mask,
+            mvp: mvp,
+            color: color,
+            highlight_color: state_params.highlight_color,
+            highlight_pulse: state_params.highlight_pulse,
+            states: (state_params.states, self.sampler.clone()),
+            colors: (state_params.colors, self.sampler.clone()),
+            fades: (state_params.fades, self.sampler.clone()),
+            use_states: use_states,
+            time: time,
+            filter_by_time: filter_by_time,
+            validity: (buffer.validity_view.clone(), self.sampler.clone()),
+            mask_output: output as i32,
+            vertex_buffer: buffer.bounding_box_vertex_buffer.clone(),
+            out_depth_stencil: (depth_stencil_target, (0, 0)),
+        };
+
+        let mut sink = EncoderSink {
+            encoder: encoder,
+            indices: indices,
+            polyhedron_pso: &self.mask.polyhedron_pso,
+            polyhedron_data: &polyhedron_data,
+            bounding_box_pso: &self.mask.bounding_box_psos[output as usize],
+            bounding_box_data: &bounding_box_data,
+        };
+
+        if states.is_some() || output == MaskOutput::PolygonId {
+            indices.draw_commands_per_polygon(&mut sink);
+        } else {
+            indices.draw_commands(&mut sink);
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that a mask with `mask` dimensions can be drawn using a depth-stencil target with
+/// `depth_stencil` dimensions.
+fn check_targets(mask: Dimensions, depth_stencil: Dimensions) -> Result<(), MaskTargetError> {
+    let (mask_width, mask_height, _, mask_aa) = mask;
+    let (depth_width, depth_height, _, depth_aa) = depth_stencil;
+
+    if mask_aa != AaMode::Single || depth_aa != AaMode::Single {
+        return Err(MaskTargetError::Multisampled);
+    }
+
+    if (mask_width, mask_height) != (depth_width, depth_height) {
+        return Err(MaskTargetError::SizeMismatch {
+            mask: (mask_width, mask_height),
+            depth_stencil: (depth_width, depth_height),
+        });
+    }
+
+    Ok(())
+}
+
+/// The error returned when `DrapingRenderer::render_mask` can't draw into the targets it is
+/// given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaskTargetError {
+    /// The mask or the depth-stencil target is multisampled. Masks are drawn single-sampled, so
+    /// they need a single-sampled depth-stencil target with the terrain drawn into it.
+    Multisampled,
+    /// The mask and the depth-stencil target differ in size, given as `(width, height)`.
+    SizeMismatch {
+        mask: (u16, u16),
+        depth_stencil: (u16, u16),
+    },
+}
+
+impl fmt::Display for MaskTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MaskTargetError::Multisampled => {
+                f.write_str("masks can't be drawn into or with multisampled targets")
+            }
+            MaskTargetError::SizeMismatch {
+                mask,
+                depth_stencil,
+            } => {
+                write!(
+                    f,
+                    "the mask is {}x{} pixels, but its depth-stencil target is {}x{}",
+                    mask.0,
+                    mask.1,
+                    depth_stencil.0,
+                    depth_stencil.1
+                )
+            }
+        }
+    }
+}
+
+impl Error for MaskTargetError {
+    fn description(&self) -> &str {
+        match *self {
+            MaskTargetError::Multisampled => "multisampled mask target",
+            MaskTargetError::SizeMismatch { .. } => "mask target size mismatch",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatched_or_multisampled_targets_are_rejected() {
+        let single = |width, height| (width, height, 1, AaMode::Single);
+        assert_eq!(check_targets(single(640, 480), single(640, 480)), Ok(()));
+
+        assert_eq!(
+            check_targets(single(320, 240), single(640, 480)),
+            Err(MaskTargetError::SizeMismatch {
+                mask: (320, 240),
+                depth_stencil: (640, 480),
+            })
+        );
+
+        let multisampled = (640, 480, 1, AaMode::Multi(4));
+        assert_eq!(
+            check_targets(single(640, 480), multisampled),
+            Err(MaskTargetError::Multisampled)
+        );
+        assert_eq!(
+            check_targets(multisampled, multisampled),
+            Err(MaskTargetError::Multisampled)
+        );
+    }
+}
@@ -1,69 +1,270 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+
 use geo;
 use geo::algorithm::boundingbox::BoundingBox;
 use gfx;
 
+use commands::{z_fail_commands, DrawSink};
+use height::HeightQuery;
 use render::*;
+use surface::{Surface, UpAxis};
+use triangulate::CapTriangulation;
 use vertex::Vertex;
 
+/// How much prisms are padded above and below, relative to their height.
+const PRISM_PADDING: f32 = 1.0 / 1024.0;
+
+/// How much oriented bounding boxes are padded, relative to their size, so that rounding never
+/// leaves part of the polygon outside them.
+const ORIENTED_BOX_PADDING: f64 = 1.0 / 65536.0;
+
+/// The largest ratio of a cover volume's area to its polygon's that `Polygon::cover_volume` picks
+/// a volume simpler than the polyhedron for.
+const MAX_COVER_AREA_RATIO: f32 = 1.5;
+
 /// A collection of polygons that could all be rendered in a single draw call.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PolygonBuffer {
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    version: SerdeVersion,
     pub(crate) polyhedron_vertices: Vec<Vertex>,
     pub(crate) bounding_box_vertices: Vec<Vertex>,
+    pub(crate) surface: Surface,
+    pub(crate) num_polygons: u32,
+    // The start and end of each polygon's validity interval, indexed by `PolygonId`.
+    pub(crate) validities: Vec<[f32; 2]>,
+    pub(crate) attributes: BTreeMap<PolygonId, BTreeMap<String, AttributeValue>>,
+    cap_triangulation: CapTriangulation,
 }
 
 impl PolygonBuffer {
     /// Create a new, empty buffer.
+    ///
+    /// Polygons added to the returned buffer are draped over a flat terrain, with Z as the
+    /// up-axis. See `with_surface` for other options.
     pub fn new() -> PolygonBuffer {
+        PolygonBuffer::with_surface(Surface::Plane(UpAxis::Z))
+    }
+
+    /// Create a new, empty buffer whose polygons are draped over `surface`.
+    ///
+    /// The surface determines how the points of polygons added to this buffer are interpreted.
+    /// For instance, use `Surface::Plane(UpAxis::Y)` if your engine is Y-up, or `Surface::Globe`
+    /// to add polygons in longitude and latitude and render them onto a planet.
+    pub fn with_surface(surface: Surface) -> PolygonBuffer {
         PolygonBuffer {
+            version: SerdeVersion,
             polyhedron_vertices: Vec::new(),
             bounding_box_vertices: Vec::new(),
+            surface: surface,
+            num_polygons: 0,
+            validities: Vec::new(),
+            attributes: BTreeMap::new(),
+            cap_triangulation: CapTriangulation::Fan,
         }
     }
 
+    /// How the caps of polygons added to this buffer from now on are triangulated. This is
+    /// initially `CapTriangulation::Fan`.
+    pub fn cap_triangulation(&self) -> CapTriangulation {
+        self.cap_triangulation
+    }
+
+    /// Triangulate the caps of polygons added to this buffer from now on with `triangulation`.
+    ///
+    /// Polygons already in the buffer keep their triangles, so this is usually called right after
+    /// creating the buffer.
+    pub fn set_cap_triangulation(&mut self, triangulation: CapTriangulation) {
+        self.cap_triangulation = triangulation;
+    }
+
     /// Add a polygon to this buffer.
     ///
     /// The `PolygonBufferIndices` returned can be used to render the passed polygon in a future
     /// call to `DrapingRenderer::render` using this buffer.
+    ///
+    /// The polygon's shadow volume will span z-values between 0 and 1, padded by a fraction of a
+    /// percent so that terrain at exactly 0 or 1 is still covered. If you know the terrain beneath
+    /// the polygon is flatter than that, use `add_with_heights` instead.
+    ///
+    /// If the polygon has a validity interval (see `Polygon::with_validity`), it is stored once
+    /// for the whole polygon, and looked up by the shaders from the polygon's ID.
     pub fn add(&mut self, polygon: &Polygon) -> PolygonBufferIndices {
+        self.add_with_z_range(polygon, (0.0, 1.0))
+    }
+
+    /// Add a polygon to this buffer, fitting its shadow volume to the terrain beneath it.
+    ///
+    /// This is like `add`, except that rather than spanning z-values between 0 and 1, the
+    /// polygon's shadow volume only spans the range of heights `heights` reports within the
+    /// polygon's bounds. Shorter shadow volumes cover fewer pixels on screen, which makes
+    /// rendering them cheaper.
+    pub fn add_with_heights<H: HeightQuery>(
+        &mut self,
+        polygon: &Polygon,
+        heights: &H,
+    ) -> PolygonBufferIndices {
+        let z_range = heights.height_range(polygon.bounds());
+        self.add_with_z_range(polygon, z_range)
+    }
+
+    fn add_with_z_range(&mut self, polygon: &Polygon, z_range: (f32, f32)) -> PolygonBufferIndices {
         let polyhedron_offset = self.polyhedron_vertices.len() as u32;
         let bounding_box_offset = self.bounding_box_vertices.len() as u32;
 
-        self.polyhedron_vertices.extend(
-            polygon.polyhedron_vertices(),
-        );
-        self.bounding_box_vertices.extend(
-            polygon.bounding_box_vertices(),
-        );
+        let polyhedron_points = self.surface.subdivide(&polygon.points);
+        let (_, cover_points) = polygon.cover();
+        let bounding_box_points = self.surface.subdivide(&cover_points);
+        let height_range = self.surface.height_range(z_range, polygon.bounds());
 
-        PolygonBufferIndices {
-            polyhedron_indices: polygon
-                .polyhedron_indices()
+        let id = PolygonId(self.num_polygons);
+        self.num_polygons += 1;
+
+        let validity = polygon.validity.unwrap_or((::std::f32::MIN, ::std::f32::MAX));
+        self.validities.push([validity.0, validity.1]);
+
+        self.polyhedron_vertices.extend(Polygon::prism_vertices(
+            &polyhedron_points,
+            height_range,
+            &self.surface,
+            id,
+        ));
+        self.bounding_box_vertices.extend(Polygon::prism_vertices(
+            &bounding_box_points,
+            height_range,
+            &self.surface,
+            id,
+        ));
+
+        let polyhedron_indices: Vec<_> =
+            Polygon::prism_indices(&polyhedron_points, self.cap_triangulation)
                 .map(|i| i + polyhedron_offset)
-                .collect(),
-            bounding_box_indices: polygon
-                .bounding_box_indices()
+                .collect();
+        let bounding_box_indices: Vec<_> =
+            Polygon::prism_indices(&bounding_box_points, self.cap_triangulation)
                 .map(|i| i + bounding_box_offset)
-                .collect(),
-        }
+                .collect();
+
+        let range = PolygonRange {
+            id: id,
+            bounds: Self::bounds(&self.bounding_box_vertices[bounding_box_offset as usize..]),
+            polyhedron_indices: 0..polyhedron_indices.len() as u32,
+            bounding_box_indices: 0..bounding_box_indices.len() as u32,
+        };
+
+        PolygonBufferIndices::from_parts(polyhedron_indices, bounding_box_indices, vec![range])
     }
 
-    /// Prepare this buffer for rendering.
+    /// Attach a named value to a polygon in this buffer, replacing any previous value with that
+    /// name.
+    ///
+    /// Attributes don't affect the polygon's geometry. They are used by `ColorRules` to choose
+    /// each polygon's color.
+    pub fn set_attribute<V: Into<AttributeValue>>(&mut self, id: PolygonId, name: &str, value: V) {
+        self.attributes
+            .entry(id)
+            .or_default()
+            .insert(name.to_owned(), value.into());
+    }
+
+    /// Get the value of a polygon's attribute, if it was set.
+    pub fn attribute(&self, id: PolygonId, name: &str) -> Option<&AttributeValue> {
+        self.attributes
+            .get(&id)
+            .and_then(|attributes| attributes.get(name))
+    }
+
+    /// Get all the attributes of a polygon, if any were set.
+    pub fn attributes(&self, id: PolygonId) -> Option<&BTreeMap<String, AttributeValue>> {
+        self.attributes.get(&id)
+    }
+
+    /// Prepare this buffer for rendering, as if with `RenderablePolygonBuffer::new`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer's vertex buffers or validity texture can't be created; use
+    /// `RenderablePolygonBuffer::try_new` to handle that instead.
     pub fn as_renderable<F: gfx::Factory<R>, R: gfx::Resources>(
         &self,
         factory: &mut F,
     ) -> RenderablePolygonBuffer<R> {
         RenderablePolygonBuffer::new(factory, &self)
     }
+
+    /// The smallest index format able to address every vertex in this buffer.
+    ///
+    /// Indices for this buffer, passed to `RenderablePolygonIndices::with_format` with this
+    /// format, will never overflow.
+    pub fn index_format(&self) -> IndexFormat {
+        let max_vertices = u16::max_value() as usize + 1;
+        if self.polyhedron_vertices.len() <= max_vertices &&
+            self.bounding_box_vertices.len() <= max_vertices
+        {
+            IndexFormat::U16
+        } else {
+            IndexFormat::U32
+        }
+    }
+
+    fn bounds(vertices: &[Vertex]) -> [[f32; 3]; 2] {
+        let mut bounds = [[::std::f32::INFINITY; 3], [::std::f32::NEG_INFINITY; 3]];
+        for vertex in vertices {
+            for (axis, &value) in vertex.position.iter().enumerate() {
+                bounds[0][axis] = bounds[0][axis].min(value);
+                bounds[1][axis] = bounds[1][axis].max(value);
+            }
+        }
+
+        bounds
+    }
 }
 
 /// A set of indices into a `PolygonBuffer`.
 ///
-/// You can combine these indices using `extend` to render multiple polygons at once.
+/// Each set of indices knows which of the buffer's polygons it contains, and behaves like a set of
+/// polygons: you can combine indices using `extend` or `union` to render multiple polygons at
+/// once, remove polygons with `difference`, and so on. A polygon is never drawn twice by the same
+/// set of indices.
+///
+/// Only indices into the same `PolygonBuffer` should be combined.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "SerializedIndices"))]
 pub struct PolygonBufferIndices {
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    version: SerdeVersion,
     pub(crate) polyhedron_indices: Vec<u32>,
     pub(crate) bounding_box_indices: Vec<u32>,
+    pub(crate) polygons: Vec<PolygonRange>,
+    /// The IDs in `polygons`, kept so that combining many sets of indices takes linear time.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    ids: HashSet<PolygonId>,
+}
+
+/// `PolygonBufferIndices` as serialized, without the IDs that can be derived from its polygons.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SerializedIndices {
+    #[allow(dead_code)]
+    version: SerdeVersion,
+    polyhedron_indices: Vec<u32>,
+    bounding_box_indices: Vec<u32>,
+    polygons: Vec<PolygonRange>,
+}
+
+#[cfg(feature = "serde")]
+impl From<SerializedIndices> for PolygonBufferIndices {
+    fn from(indices: SerializedIndices) -> PolygonBufferIndices {
+        PolygonBufferIndices::from_parts(
+            indices.polyhedron_indices,
+            indices.bounding_box_indices,
+            indices.polygons,
+        )
+    }
 }
 
 impl PolygonBufferIndices {
@@ -72,9 +273,20 @@ impl PolygonBufferIndices {
     /// Rendering the returned indices would be a no-op unless you call `extend` on it. This is a
     /// convenience method that you can use as the "zero" value to a `reduce`-like operation.
     pub fn new() -> PolygonBufferIndices {
+        PolygonBufferIndices::from_parts(Vec::new(), Vec::new(), Vec::new())
+    }
+
+    pub(crate) fn from_parts(
+        polyhedron_indices: Vec<u32>,
+        bounding_box_indices: Vec<u32>,
+        polygons: Vec<PolygonRange>,
+    ) -> PolygonBufferIndices {
         PolygonBufferIndices {
-            polyhedron_indices: Vec::new(),
-            bounding_box_indices: Vec::new(),
+            version: SerdeVersion,
+            polyhedron_indices: polyhedron_indices,
+            bounding_box_indices: bounding_box_indices,
+            ids: polygons.iter().map(|polygon| polygon.id).collect(),
+            polygons: polygons,
         }
     }
 
@@ -82,17 +294,117 @@ impl PolygonBufferIndices {
     ///
     /// After calling `extend`, rendering `this` will draw all the polygons previously in `this` as
     /// well as all the polygons in `other`. In other words, you can think of this as a
-    /// "union"/"add all" operation.
+    /// "union"/"add all" operation. Polygons already in `this` are not added again.
     pub fn extend(&mut self, other: &PolygonBufferIndices) {
+        for polygon in &other.polygons {
+            if !self.ids.contains(&polygon.id) {
+                self.push(other, polygon);
+            }
+        }
+    }
+
+    /// Get the polygons in either `self` or `other`.
+    pub fn union(&self, other: &PolygonBufferIndices) -> PolygonBufferIndices {
+        let mut union = self.clone();
+        union.extend(other);
+        union
+    }
+
+    /// Get the polygons in `self` but not in `other`.
+    pub fn difference(&self, other: &PolygonBufferIndices) -> PolygonBufferIndices {
+        self.filter(|id| !other.ids.contains(&id))
+    }
+
+    /// Get the polygons in both `self` and `other`.
+    pub fn intersection(&self, other: &PolygonBufferIndices) -> PolygonBufferIndices {
+        self.filter(|id| other.ids.contains(&id))
+    }
+
+    /// Whether every polygon in `other` is also in `self`.
+    pub fn contains(&self, other: &PolygonBufferIndices) -> bool {
+        other.ids.is_subset(&self.ids)
+    }
+
+    /// Whether the polygon with the given ID is in `self`.
+    pub fn contains_polygon(&self, id: PolygonId) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// The IDs of the polygons in `self`, in the order they are drawn.
+    pub fn polygon_ids<'a>(&'a self) -> Box<dyn Iterator<Item = PolygonId> + 'a> {
+        Box::new(self.polygons.iter().map(|polygon| polygon.id))
+    }
+
+    /// The number of polygons these indices draw.
+    pub fn len(&self) -> usize {
+        self.polygons.len()
+    }
+
+    /// Whether these indices draw no polygons at all.
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    /// The number of triangles drawn when rendering these indices, as `(polyhedron_triangles,
+    /// bounding_box_triangles)`.
+    pub fn triangle_counts(&self) -> (usize, usize) {
+        (
+            self.polyhedron_indices.len() / 3,
+            self.bounding_box_indices.len() / 3,
+        )
+    }
+
+    /// Pass `sink` the commands that render these indices, drawing each geometry's indices in a
+    /// single range.
+    pub fn draw_commands<S: DrawSink>(&self, sink: &mut S) {
+        let polyhedron_range = 0..self.polyhedron_indices.len() as u32;
+        let bounding_box_range = 0..self.bounding_box_indices.len() as u32;
+        z_fail_commands(sink, &[polyhedron_range], &[bounding_box_range]);
+    }
+
+    /// The world-space bounds of each polygon's cover volume, as `[min, max]`.
+    pub fn polygon_bounds<'a>(&'a self) -> Box<dyn Iterator<Item = [[f32; 3]; 2]> + 'a> {
+        Box::new(self.polygons.iter().map(|polygon| polygon.bounds))
+    }
+
+    /// Get the polygons in `self` whose IDs satisfy `predicate`.
+    pub(crate) fn filter<P: Fn(PolygonId) -> bool>(&self, predicate: P) -> PolygonBufferIndices {
+        let mut result = PolygonBufferIndices::new();
+        for polygon in &self.polygons {
+            if predicate(polygon.id) {
+                result.push(self, polygon);
+            }
+        }
+
+        result
+    }
+
+    /// Append `polygon`, whose indices are in `source`, to `self`.
+    pub(crate) fn push(&mut self, source: &PolygonBufferIndices, polygon: &PolygonRange) {
+        let polyhedron_start = self.polyhedron_indices.len() as u32;
+        let bounding_box_start = self.bounding_box_indices.len() as u32;
+
         self.polyhedron_indices.extend_from_slice(
-            &other.polyhedron_indices,
+            &source.polyhedron_indices[polygon.polyhedron_indices.start as usize..
+                                           polygon.polyhedron_indices.end as usize],
         );
         self.bounding_box_indices.extend_from_slice(
-            &other.bounding_box_indices,
+            &source.bounding_box_indices[polygon.bounding_box_indices.start as usize..
+                                             polygon.bounding_box_indices.end as usize],
         );
+
+        self.ids.insert(polygon.id);
+        self.polygons.push(PolygonRange {
+            id: polygon.id,
+            bounds: polygon.bounds,
+            polyhedron_indices: polyhedron_start..self.polyhedron_indices.len() as u32,
+            bounding_box_indices: bounding_box_start..self.bounding_box_indices.len() as u32,
+        });
     }
 
     /// Prepare these indices for rendering.
+    ///
+    /// See `RenderablePolygonIndices::new` for how the index format is chosen.
     pub fn as_renderable<F: gfx::Factory<R>, R: gfx::Resources>(
         &self,
         factory: &mut F,
@@ -101,14 +413,80 @@ impl PolygonBufferIndices {
     }
 }
 
+/// Identifies a polygon within a `PolygonBuffer`.
+///
+/// Polygons are numbered from zero in the order they are added to the buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PolygonId(pub u32);
+
+/// The value of a polygon attribute.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AttributeValue {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+}
+
+impl AttributeValue {
+    /// The value as a number, if it is one.
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            AttributeValue::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+}
+
+impl From<f64> for AttributeValue {
+    fn from(number: f64) -> AttributeValue {
+        AttributeValue::Number(number)
+    }
+}
+
+impl<'a> From<&'a str> for AttributeValue {
+    fn from(text: &'a str) -> AttributeValue {
+        AttributeValue::Text(text.to_owned())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(text: String) -> AttributeValue {
+        AttributeValue::Text(text)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> AttributeValue {
+        AttributeValue::Bool(value)
+    }
+}
+
+/// The part of a `PolygonBufferIndices` that belongs to a single polygon.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(crate) struct PolygonRange {
+    pub id: PolygonId,
+    /// The axis-aligned bounds, as `[min, max]`, of the polygon's cover volume.
+    pub bounds: [[f32; 3]; 2],
+    pub polyhedron_indices: Range<u32>,
+    pub bounding_box_indices: Range<u32>,
+}
+
 /// A polygon with a bounding box.
 ///
 /// This struct implements `From<geoo:Polygon>`, so for GIS applications you can instantiate this
 /// from any `geo::Polygon`.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Polygon {
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    version: SerdeVersion,
     bounding_ring: [(f32, f32); 5],
     points: Vec<(f32, f32)>,
+    validity: Option<(f32, f32)>,
+    cover: Option<CoverVolume>,
 }
 
 impl Polygon {
@@ -131,74 +509,485 @@ impl Polygon {
         ];
 
         Polygon {
+            version: SerdeVersion,
             bounding_ring: bounding_ring,
             points: points,
+            validity: None,
+            cover: None,
         }
     }
 
-    fn bounding_box_vertices<'a>(&'a self) -> Box<'a + Iterator<Item = Vertex>> {
-        Box::new(Self::prism_vertices(&self.bounding_ring))
+    /// Only draw this polygon at times from `start` up to, but not including, `end`.
+    ///
+    /// Polygons without a validity interval are drawn at all times. The time is passed to the
+    /// renderer with `RenderParams::with_time`, so that scrubbing through a timeline doesn't
+    /// require rebuilding any buffers.
+    pub fn with_validity(mut self, start: f32, end: f32) -> Polygon {
+        self.validity = Some((start, end));
+        self
+    }
+
+    /// The times this polygon is drawn at, as `(start, end)`, if they are limited.
+    pub fn validity(&self) -> Option<(f32, f32)> {
+        self.validity
+    }
+
+    /// Draw the cover pass of this polygon with `cover`, rather than the volume `cover_volume`
+    /// would choose.
+    ///
+    /// If the polygon is too degenerate to have that volume, such as a convex hull of collinear
+    /// points, its bounding box is used instead.
+    pub fn with_cover_volume(mut self, cover: CoverVolume) -> Polygon {
+        self.cover = Some(cover);
+        self
+    }
+
+    /// The volume drawn for this polygon in the cover pass.
+    ///
+    /// Unless one was chosen with `with_cover_volume`, this is the first of the bounding box, the
+    /// oriented bounding box and the convex hull whose area is at most one and a half times the
+    /// polygon's, or the polyhedron if none is. Areas are measured in the plane of the polygon's
+    /// points, even on a `Surface::Globe`.
+    pub fn cover_volume(&self) -> CoverVolume {
+        self.cover().0
+    }
+
+    /// The bounds of this polygon, as `[(min_x, max_x), (min_y, max_y)]`.
+    pub fn bounds(&self) -> [(f32, f32); 2] {
+        [
+            (self.bounding_ring[0].0, self.bounding_ring[2].0),
+            (self.bounding_ring[0].1, self.bounding_ring[2].1),
+        ]
     }
 
-    fn bounding_box_indices(&self) -> Box<Iterator<Item = u32>> {
-        Self::prism_indices(5)
+    /// A polygon covering a band `width` wide along every ring of this polygon.
+    ///
+    /// The outline has the same validity interval as this polygon.
+    ///
+    /// Add the result to the same `PolygonBuffer` as this polygon to draw its outline. `width` is
+    /// in the same units as the polygon's points, so on a `Surface::Globe` it is in degrees.
+    ///
+    /// Each edge becomes its own rectangle, extended by half of `width` at both ends so that the
+    /// corners between edges are filled in. Each rectangle is a ring of the outline, and is drawn
+    /// as a volume of its own, so rectangles that overlap at corners are drawn just once and
+    /// nothing between them is filled.
+    pub fn outline(&self, width: f32) -> Polygon {
+        let half_width = width / 2.0;
+        let mut points = Vec::new();
+
+        for ring in rings(&self.points) {
+            for edge in ring.windows(2) {
+                let (from, to) = (edge[0], edge[1]);
+                let length = (to.0 - from.0).hypot(to.1 - from.1);
+                if length == 0.0 {
+                    continue;
+                }
+
+                // Along the edge, and to its left, each half of `width` long.
+                let along = (
+                    (to.0 - from.0) / length * half_width,
+                    (to.1 - from.1) / length * half_width,
+                );
+                let left = (-along.1, along.0);
+
+                let start = (from.0 - along.0, from.1 - along.1);
+                let end = (to.0 + along.0, to.1 + along.1);
+                let corner = |point: (f32, f32), sign: f32| {
+                    (point.0 + left.0 * sign, point.1 + left.1 * sign)
+                };
+
+                points.extend(vec![
+                    corner(start, -1.0),
+                    corner(end, -1.0),
+                    corner(end, 1.0),
+                    corner(start, 1.0),
+                    corner(start, -1.0),
+                ]);
+            }
+        }
+
+        // The rectangles' corners stick out by at most `half_width * sqrt(2)`.
+        let bounds = self.bounds();
+        let mut outline = Polygon::new(
+            [
+                (bounds[0].0 - width, bounds[0].1 + width),
+                (bounds[1].0 - width, bounds[1].1 + width),
+            ],
+            points,
+        );
+
+        outline.validity = self.validity;
+        outline
     }
 
-    fn polyhedron_vertices<'a>(&'a self) -> Box<'a + Iterator<Item = Vertex>> {
-        Self::prism_vertices(&self.points)
+    /// A polygon covering a band `width` wide just outside every ring of this polygon, for
+    /// softening its edges with `DrapingRenderer::render_edge_fade`.
+    ///
+    /// The band has the same validity interval as this polygon, and `width` is in the same units
+    /// as its points. For the band to look like antialiasing rather than an outline, `width`
+    /// should be about the size of a pixel at the distance the polygon is usually seen from.
+    ///
+    /// Each edge becomes its own rectangle, on the edge's right, which is outside the polygon if
+    /// its rings are oriented as `Polygon::new` asks. At convex corners, rectangles are extended
+    /// by `width` so that the corner is filled in. Each rectangle is a ring of the band, and is
+    /// drawn as a volume of its own, so the polygon inside the band is never filled.
+    pub fn edge_fade(&self, width: f32) -> Polygon {
+        let mut points = Vec::new();
+
+        for ring in rings(&self.points) {
+            // Rings repeat their first point at the end, so the last edge is followed by the
+            // first.
+            let edges: Vec<_> = ring.windows(2)
+                .map(|edge| (edge[0], edge[1]))
+                .filter(|&(from, to)| from != to)
+                .collect();
+
+            for (index, &(from, to)) in edges.iter().enumerate() {
+                let length = (to.0 - from.0).hypot(to.1 - from.1);
+                let along = ((to.0 - from.0) / length, (to.1 - from.1) / length);
+                let right = (along.1 * width, -along.0 * width);
+
+                // A corner is convex if the ring turns left there, towards the polygon.
+                let direction = |(from, to): ((f32, f32), (f32, f32))| {
+                    (to.0 - from.0, to.1 - from.1)
+                };
+                let turns_left = |a, b| {
+                    let (a, b) = (direction(a), direction(b));
+                    a.0 * b.1 - a.1 * b.0 > 0.0
+                };
+                let previous = edges[(index + edges.len() - 1) % edges.len()];
+                let next = edges[(index + 1) % edges.len()];
+                let extend_start = if turns_left(previous, (from, to)) { width } else { 0.0 };
+                let extend_end = if turns_left((from, to), next) { width } else { 0.0 };
+
+                let start = (from.0 - along.0 * extend_start, from.1 - along.1 * extend_start);
+                let end = (to.0 + along.0 * extend_end, to.1 + along.1 * extend_end);
+
+                points.extend(vec![
+                    (start.0 + right.0, start.1 + right.1),
+                    (end.0 + right.0, end.1 + right.1),
+                    end,
+                    start,
+                    (start.0 + right.0, start.1 + right.1),
+                ]);
+            }
+        }
+
+        // The rectangles stick out by at most `width * sqrt(2)`.
+        let bounds = self.bounds();
+        let margin = width * 2.0;
+        let mut edge_fade = Polygon::new(
+            [
+                (bounds[0].0 - margin, bounds[0].1 + margin),
+                (bounds[1].0 - margin, bounds[1].1 + margin),
+            ],
+            points,
+        );
+
+        edge_fade.validity = self.validity;
+        edge_fade
     }
 
-    fn polyhedron_indices(&self) -> Box<Iterator<Item = u32>> {
-        Self::prism_indices(self.points.len() as u32)
+    /// This polygon's cover volume, and the rings it is a prism over.
+    fn cover(&self) -> (CoverVolume, Vec<(f32, f32)>) {
+        let hull = convex_hull(&self.points);
+        let oriented_box = oriented_bounding_box(&hull);
+
+        // Listed from the fewest vertices to the most.
+        let candidates = vec![
+            (CoverVolume::BoundingBox, Some(self.bounding_ring.to_vec())),
+            (CoverVolume::OrientedBoundingBox, oriented_box),
+            (CoverVolume::ConvexHull, Some(hull).filter(|hull| hull.len() > 3)),
+            (CoverVolume::Polyhedron, Some(self.points.clone())),
+        ];
+
+        let max_area = area(&self.points) * MAX_COVER_AREA_RATIO;
+        let chosen = candidates.into_iter().find(|&(cover, ref points)| {
+            match (self.cover, points) {
+                (Some(chosen), Some(_)) => cover == chosen,
+                (None, Some(points)) => {
+                    cover == CoverVolume::Polyhedron || area(points) <= max_area
+                }
+                (_, None) => false,
+            }
+        });
+
+        match chosen {
+            Some((cover, Some(points))) => (cover, points),
+            _ => (CoverVolume::BoundingBox, self.bounding_ring.to_vec()),
+        }
     }
 
-    fn prism_vertices<'a>(points: &'a [(f32, f32)]) -> Box<'a + Iterator<Item = Vertex>> {
-        Box::new(points.iter().flat_map(move |&(x, y)| {
-            let below = Vertex { position: [x, y, 0.0] };
-            let above = Vertex { position: [x, y, 1.0] };
+    fn prism_vertices<'a>(
+        points: &'a [(f32, f32)],
+        (min_height, max_height): (f32, f32),
+        surface: &'a Surface,
+        id: PolygonId,
+    ) -> Box<dyn Iterator<Item = Vertex> + 'a> {
+        // Terrain lying exactly on the top or bottom of the prism fights with it in the depth
+        // test, so the prism is padded by a small fraction of its height or of its distance from
+        // zero, whichever is larger, and at least of one unit.
+        let scale = (max_height - min_height)
+            .max(min_height.abs())
+            .max(max_height.abs())
+            .max(1.0);
+        let padding = scale * PRISM_PADDING;
+        let (min_height, max_height) = (min_height - padding, max_height + padding);
+
+        Box::new(points.iter().flat_map(move |&point| {
+            let below = Vertex {
+                position: surface.position(point, min_height),
+                polygon_id: id.0,
+            };
+            let above = Vertex {
+                position: surface.position(point, max_height),
+                polygon_id: id.0,
+            };
             vec![below, above]
         }))
     }
 
-    fn prism_indices(num_points: u32) -> Box<Iterator<Item = u32>> {
-        Box::new((0..num_points).flat_map(move |index| {
+    fn prism_indices(
+        points: &[(f32, f32)],
+        triangulation: CapTriangulation,
+    ) -> Box<dyn Iterator<Item = u32>> {
+        // Each ring is walled off separately. Joining one ring to the next would wall off the
+        // polygon between the rings' first points too, which is only empty with a single hole.
+        let mut edges = Vec::with_capacity(points.len());
+        let mut start = 0;
+        for ring in rings(points) {
+            let end = start + ring.len() as u32;
+            edges.extend((start..end - 1).map(|index| (index, index + 1)));
+            if ring.first() != ring.last() {
+                edges.push((end - 1, start));
+            }
+
+            start = end;
+        }
+
+        let walls = edges.into_iter().flat_map(|(index, next_index)| {
             let below_index = 2 * index;
             let above_index = below_index + 1;
-            let after_below_index = 2 * ((1 + index) % num_points);
+            let after_below_index = 2 * next_index;
             let after_above_index = after_below_index + 1;
 
             // When on an exterior ring, whose points are in counter-clockwise orientation,
             // this face should face outward.
             //
             // For interior rings, with clockwise orientation, this face should face inward.
-            let mut indices = vec![
+            vec![
                 below_index,
                 after_below_index,
                 above_index,
                 after_below_index,
                 after_above_index,
                 above_index,
-            ];
-
-            if index != 0 && index != num_points - 1 {
-                // The top faces should face upward; the bottom faces, downward.
-                let cap_triangles = vec![
-                    0,
-                    after_below_index,
-                    below_index,
-                    1,
-                    above_index,
-                    after_above_index,
-                ];
-
-                indices.extend(cap_triangles);
+            ]
+        });
+
+        // The triangulation is counter-clockwise, and the top faces should face upward; the
+        // bottom faces, downward.
+        let caps = triangulation
+            .triangulate(points)
+            .chunks(3)
+            .flat_map(|triangle| {
+                let (a, b, c) = (2 * triangle[0], 2 * triangle[1], 2 * triangle[2]);
+                vec![a, c, b, a + 1, b + 1, c + 1]
+            })
+            .collect::<Vec<_>>();
+
+        Box::new(walls.chain(caps))
+    }
+}
+
+/// The volume drawn for a polygon in the cover pass, which colors the terrain wherever the first
+/// pass found it to lie inside the polygon.
+///
+/// Every pixel the volume covers on screen is stencil-tested, so a volume hugging the polygon
+/// more tightly is cheaper to fill, though it may have more vertices. See
+/// `Polygon::cover_volume`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CoverVolume {
+    /// A prism over the polygon's axis-aligned bounds. This has the fewest vertices, and suits
+    /// polygons that fill most of their bounds.
+    BoundingBox,
+    /// A prism over the smallest rectangle, at any angle, enclosing the polygon. This suits long
+    /// polygons lying diagonally.
+    OrientedBoundingBox,
+    /// A prism over the polygon's convex hull.
+    ConvexHull,
+    /// The polygon's own polyhedron, which covers no more than the polygon, at the cost of
+    /// drawing its vertices twice. This suits polygons with deep concavities, such as L shapes and
+    /// outlines.
+    Polyhedron,
+}
+
+/// The area enclosed by `points`, a concatenation of rings as passed to `Polygon::new`. Holes,
+/// being clockwise, subtract from it.
+fn area(points: &[(f32, f32)]) -> f32 {
+    let twice_area: f32 = rings(points)
+        .iter()
+        .flat_map(|ring| ring.windows(2))
+        .map(|edge| edge[0].0 * edge[1].1 - edge[1].0 * edge[0].1)
+        .sum();
+
+    twice_area / 2.0
+}
+
+/// The convex hull of `points`, as a counter-clockwise ring, which has fewer than four points if
+/// `points` are all collinear.
+fn convex_hull(points: &[(f32, f32)]) -> Vec<(f32, f32)> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    sorted.dedup();
+
+    // Andrew's monotone chain: the lower half of the hull from left to right, then the upper
+    // half from right to left, each dropping points where the hull doesn't turn left.
+    let cross = |o: (f32, f32), a: (f32, f32), b: (f32, f32)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+    let mut hull: Vec<(f32, f32)> = Vec::new();
+    for half in &[sorted.clone(), sorted.iter().rev().cloned().collect()] {
+        let start = hull.len();
+        for &point in half {
+            while hull.len() >= start + 2 &&
+                cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.0
+            {
+                hull.pop();
             }
+            hull.push(point);
+        }
 
-            indices
-        }))
+        // Each half ends where the other starts.
+        hull.pop();
+    }
+
+    if let Some(&first) = hull.first() {
+        hull.push(first);
+    }
+
+    hull
+}
+
+/// The smallest rectangle enclosing `hull`, a convex hull as returned by `convex_hull`, as a
+/// counter-clockwise ring.
+fn oriented_bounding_box(hull: &[(f32, f32)]) -> Option<Vec<(f32, f32)>> {
+    if hull.len() <= 3 {
+        return None;
+    }
+
+    // The smallest rectangle has a side along one of the hull's edges. Points are taken relative
+    // to the first, in double precision, to limit rounding.
+    let origin = (hull[0].0 as f64, hull[0].1 as f64);
+    let points: Vec<_> = hull.iter()
+        .map(|&(x, y)| (x as f64 - origin.0, y as f64 - origin.1))
+        .collect();
+
+    let mut best: Option<(f64, Vec<(f32, f32)>)> = None;
+    for edge in points.windows(2) {
+        let (dx, dy) = (edge[1].0 - edge[0].0, edge[1].1 - edge[0].1);
+        let length = dx.hypot(dy);
+        if length == 0.0 {
+            continue;
+        }
+
+        // Along the edge, and to its left.
+        let u = (dx / length, dy / length);
+        let v = (-u.1, u.0);
+
+        let mut min = (::std::f64::INFINITY, ::std::f64::INFINITY);
+        let mut max = (::std::f64::NEG_INFINITY, ::std::f64::NEG_INFINITY);
+        for &(x, y) in &points {
+            let (along, left) = (x * u.0 + y * u.1, x * v.0 + y * v.1);
+            min = (min.0.min(along), min.1.min(left));
+            max = (max.0.max(along), max.1.max(left));
+        }
+
+        let area = (max.0 - min.0) * (max.1 - min.1);
+        if best.as_ref().filter(|best| best.0 <= area).is_some() {
+            continue;
+        }
+
+        let padding = (max.0 - min.0).max(max.1 - min.1) * ORIENTED_BOX_PADDING;
+        let (min, max) = ((min.0 - padding, min.1 - padding), (max.0 + padding, max.1 + padding));
+        let corner = |along: f64, left: f64| {
+            (
+                (origin.0 + u.0 * along + v.0 * left) as f32,
+                (origin.1 + u.1 * along + v.1 * left) as f32,
+            )
+        };
+
+        let ring = vec![
+            corner(min.0, min.1),
+            corner(max.0, min.1),
+            corner(max.0, max.1),
+            corner(min.0, max.1),
+            corner(min.0, min.1),
+        ];
+        best = Some((area, ring));
+    }
+
+    best.map(|(_, ring)| ring)
+}
+
+/// The version of the format `PolygonBuffer`, `PolygonBufferIndices` and `Polygon` are serialized
+/// in. Bump this whenever a field is added, removed or changes meaning.
+#[cfg(feature = "serde")]
+const SERDE_VERSION: u32 = 1;
+
+/// Serialized first in each versioned struct, so that data written in another format version is
+/// rejected with an error instead of being misread. Data serialized without a version, from before
+/// the format was versioned, fails with a missing field error.
+#[derive(Clone, Copy, Debug)]
+struct SerdeVersion;
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for SerdeVersion {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(SERDE_VERSION)
     }
 }
 
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for SerdeVersion {
+    fn deserialize<D>(deserializer: D) -> Result<SerdeVersion, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let version = u32::deserialize(deserializer)?;
+        if version == SERDE_VERSION {
+            Ok(SerdeVersion)
+        } else {
+            Err(D::Error::custom(format!(
+                "serialized with format version {}, but only version {} can be read",
+                version, SERDE_VERSION
+            )))
+        }
+    }
+}
+
+/// Split `points`, a concatenation of rings as passed to `Polygon::new`, into its rings.
+pub(crate) fn rings(points: &[(f32, f32)]) -> Vec<&[(f32, f32)]> {
+    let mut rings = Vec::new();
+    let mut start = 0;
+
+    while start < points.len() {
+        let end = points[start + 1..]
+            .iter()
+            .position(|point| *point == points[start])
+            .map(|offset| start + offset + 2)
+            .unwrap_or(points.len());
+
+        rings.push(&points[start..end]);
+        start = end;
+    }
+
+    rings
+}
+
 impl From<geo::Polygon<f32>> for Polygon {
     fn from(polygon: geo::Polygon<f32>) -> Polygon {
         let bounding_box = polygon.bbox().unwrap();
@@ -217,3 +1006,207 @@ impl From<geo::Polygon<f32>> for Polygon {
         Polygon::new(bounds, points)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use commands::{DrawCommand, DrawRecorder, Pass};
+
+    use super::*;
+
+    fn square(min: (f32, f32), size: f32) -> Vec<(f32, f32)> {
+        let max = (min.0 + size, min.1 + size);
+        vec![min, (max.0, min.1), max, (min.0, max.1), min]
+    }
+
+    fn hole(min: (f32, f32), size: f32) -> Vec<(f32, f32)> {
+        let mut ring = square(min, size);
+        ring.reverse();
+        ring
+    }
+
+    /// How many times the top caps of the polyhedra in `indices` wind around `point`. Polygons
+    /// are drawn wherever this is nonzero.
+    fn winding(buffer: &PolygonBuffer, indices: &PolygonBufferIndices, point: (f32, f32)) -> i32 {
+        let position = |index: &u32| buffer.polyhedron_vertices[*index as usize].position;
+        let side = |a: [f32; 3], b: [f32; 3]| {
+            let side = (b[0] - a[0]) * (point.1 - a[1]) - (b[1] - a[1]) * (point.0 - a[0]);
+            side.signum() as i32
+        };
+
+        indices
+            .polyhedron_indices
+            .chunks(3)
+            .map(|triangle| triangle.iter().map(position).collect::<Vec<_>>())
+            .filter(|triangle| triangle.iter().all(|position| position[2] > 0.5))
+            .map(|t| {
+                let sides = [side(t[0], t[1]), side(t[1], t[2]), side(t[2], t[0])];
+                if sides.iter().all(|&s| s == sides[0]) {
+                    sides[0]
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+
+    /// Whether every edge of every face of the polyhedra in `indices` is matched by an edge
+    /// running the other way, which z-fail stenciling needs for its counts to balance.
+    fn is_closed(buffer: &PolygonBuffer, indices: &PolygonBufferIndices) -> bool {
+        let key = |index: u32| {
+            let position = buffer.polyhedron_vertices[index as usize].position;
+            [
+                position[0].to_bits(),
+                position[1].to_bits(),
+                position[2].to_bits(),
+            ]
+        };
+
+        let mut edges = HashMap::new();
+        for triangle in indices.polyhedron_indices.chunks(3) {
+            let corners = [key(triangle[0]), key(triangle[1]), key(triangle[2])];
+            for i in 0..3 {
+                let (from, to) = (corners[i], corners[(i + 1) % 3]);
+                if from != to {
+                    *edges.entry((from, to)).or_insert(0) += 1;
+                    *edges.entry((to, from)).or_insert(0) -= 1;
+                }
+            }
+        }
+
+        edges.values().all(|&count| count == 0)
+    }
+
+    #[test]
+    fn polygons_with_several_holes_only_cover_their_interior() {
+        // The holes' first points form a triangle around (4.1, 3.3).
+        let mut points = square((0.0, 0.0), 10.0);
+        points.extend(hole((1.0, 1.0), 1.0));
+        points.extend(hole((7.0, 2.0), 1.0));
+        points.extend(hole((4.0, 7.0), 1.0));
+        let polygon = Polygon::new([(0.0, 10.0), (0.0, 10.0)], points);
+
+        let mut buffer = PolygonBuffer::new();
+        let indices = buffer.add(&polygon);
+
+        assert!(is_closed(&buffer, &indices));
+        assert_eq!(winding(&buffer, &indices, (4.1, 3.3)), 1);
+        assert_eq!(winding(&buffer, &indices, (0.5, 9.3)), 1);
+        assert_eq!(winding(&buffer, &indices, (1.5, 1.4)), 0);
+        assert_eq!(winding(&buffer, &indices, (7.5, 2.4)), 0);
+        assert_eq!(winding(&buffer, &indices, (4.5, 7.4)), 0);
+        assert_eq!(winding(&buffer, &indices, (11.0, 5.1)), 0);
+    }
+
+    #[test]
+    fn indices_draw_all_their_polyhedra_before_their_bounding_boxes() {
+        let mut buffer = PolygonBuffer::new();
+        let mut indices = PolygonBufferIndices::new();
+        for i in 0..3 {
+            let min = (2.0 * i as f32, 0.0);
+            indices.extend(&buffer.add(&Polygon::new([min, (min.0 + 1.0, 1.0)], square(min, 1.0))));
+        }
+        let rest = indices.filter(|id| id != PolygonId(1));
+
+        let mut recorder = DrawRecorder::new();
+        rest.draw_commands(&mut recorder);
+        assert_eq!(
+            recorder.commands(),
+            &[
+                DrawCommand {
+                    pass: Pass::Polyhedron,
+                    indices: 0..rest.polyhedron_indices.len() as u32,
+                },
+                DrawCommand {
+                    pass: Pass::Cover,
+                    indices: 0..rest.bounding_box_indices.len() as u32,
+                },
+            ]
+        );
+        assert_eq!(3 * rest.polyhedron_indices.len(), 2 * indices.polyhedron_indices.len());
+
+        recorder.clear();
+        PolygonBufferIndices::new().draw_commands(&mut recorder);
+        assert!(recorder.commands().is_empty());
+    }
+
+    #[test]
+    fn extending_indices_skips_polygons_already_in_them() {
+        let mut buffer = PolygonBuffer::new();
+        let singles: Vec<_> = (0..4)
+            .map(|i| {
+                let min = (2.0 * i as f32, 0.0);
+                buffer.add(&Polygon::new([min, (min.0 + 1.0, 1.0)], square(min, 1.0)))
+            })
+            .collect();
+
+        let mut all = PolygonBufferIndices::new();
+        for indices in singles.iter().chain(&singles) {
+            all.extend(indices);
+        }
+
+        let ids: Vec<_> = all.polygon_ids().collect();
+        assert_eq!(ids, (0..4).map(PolygonId).collect::<Vec<_>>());
+        assert_eq!(all.triangle_counts().0, 4 * singles[0].triangle_counts().0);
+
+        let rest = all.difference(&singles[1]);
+        assert!(all.contains(&rest) && !rest.contains(&all));
+        assert!(!rest.contains_polygon(PolygonId(1)) && rest.contains_polygon(PolygonId(2)));
+        assert_eq!(rest.intersection(&singles[3]).polygon_ids().collect::<Vec<_>>(), vec![
+            PolygonId(3),
+        ]);
+    }
+
+    #[test]
+    fn outlines_only_cover_a_band_along_each_ring() {
+        let mut points = square((0.0, 0.0), 10.0);
+        points.extend(hole((4.0, 4.0), 2.0));
+        let outline = Polygon::new([(0.0, 10.0), (0.0, 10.0)], points).outline(1.0);
+
+        let mut buffer = PolygonBuffer::new();
+        let indices = buffer.add(&outline);
+
+        assert!(is_closed(&buffer, &indices));
+        for &point in &[(0.3, 5.1), (-0.3, 5.1), (5.1, 9.8), (-0.3, -0.4), (4.2, 5.1)] {
+            assert!(winding(&buffer, &indices, point) > 0, "{:?}", point);
+        }
+
+        for &point in &[(2.1, 2.3), (7.9, 8.1), (5.1, 5.3), (-0.7, 5.1), (11.0, 11.0)] {
+            assert_eq!(winding(&buffer, &indices, point), 0, "{:?}", point);
+        }
+    }
+
+    #[test]
+    fn edge_fades_only_cover_a_band_outside_each_ring() {
+        let mut points = square((0.0, 0.0), 10.0);
+        points.extend(hole((3.0, 3.0), 4.0));
+        let edge_fade = Polygon::new([(0.0, 10.0), (0.0, 10.0)], points).edge_fade(1.0);
+
+        let mut buffer = PolygonBuffer::new();
+        let indices = buffer.add(&edge_fade);
+
+        assert!(is_closed(&buffer, &indices));
+        for &point in &[(5.1, -0.4), (10.3, 5.1), (-0.6, 10.7), (-0.4, -0.3), (5.1, 3.3)] {
+            assert!(winding(&buffer, &indices, point) > 0, "{:?}", point);
+        }
+
+        for &point in &[(5.1, 0.4), (2.1, 2.3), (9.7, 5.1), (5.1, 5.3), (5.1, 2.7), (5.1, -1.4)] {
+            assert_eq!(winding(&buffer, &indices, point), 0, "{:?}", point);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn other_serialized_format_versions_are_rejected() {
+        use serde::de::value::{Error, U32Deserializer};
+        use serde::de::IntoDeserializer;
+        use serde::Deserialize;
+
+        let current: U32Deserializer<Error> = SERDE_VERSION.into_deserializer();
+        assert!(SerdeVersion::deserialize(current).is_ok());
+
+        let next: U32Deserializer<Error> = (SERDE_VERSION + 1).into_deserializer();
+        assert!(SerdeVersion::deserialize(next).is_err());
+    }
+}
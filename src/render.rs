@@ -1,11 +1,28 @@
+use std::error::Error;
+use std::fmt;
+use std::iter;
+use std::ops::Range;
+
 use gfx;
 use gfx::traits::FactoryExt;
 
+use commands::*;
+use deferred::DeferredPipelines;
+use fill::FillShader;
+use frustum::Frustum;
+use glsl::*;
+use mask::MaskPipelines;
 use polygon::*;
+use states::{texture_size, PolygonStates};
 use vertex::Vertex;
 
 gfx_pipeline!(z_fail_polyhedron_pipeline {
     mvp: gfx::Global<[[f32; 4]; 4]> = "u_mvp",
+    states: gfx::TextureSampler<[f32; 4]> = "t_states",
+    use_states: gfx::Global<i32> = "u_use_states",
+    time: gfx::Global<f32> = "u_time",
+    filter_by_time: gfx::Global<i32> = "u_filter_by_time",
+    validity: gfx::TextureSampler<[f32; 4]> = "t_validity",
     vertex_buffer: gfx::VertexBuffer<Vertex> = (),
     out_color: gfx::BlendTarget<gfx::format::Srgba8> = (
         "o_color",
@@ -14,24 +31,7 @@ gfx_pipeline!(z_fail_polyhedron_pipeline {
     ),
     out_depth_stencil: gfx::DepthStencilTarget<gfx::format::DepthStencil> = (
         gfx::preset::depth::LESS_EQUAL_TEST,
-        gfx::state::Stencil {
-            front: gfx::state::StencilSide {
-                fun: gfx::state::Comparison::Always,
-                mask_read: 255,
-                mask_write: 255,
-                op_fail: gfx::state::StencilOp::Keep,
-                op_depth_fail: gfx::state::StencilOp::DecrementWrap,
-                op_pass: gfx::state::StencilOp::Keep,
-            },
-            back: gfx::state::StencilSide {
-                fun: gfx::state::Comparison::Always,
-                mask_read: 255,
-                mask_write: 255,
-                op_fail: gfx::state::StencilOp::Keep,
-                op_depth_fail: gfx::state::StencilOp::IncrementWrap,
-                op_pass: gfx::state::StencilOp::Keep,
-            },
-        },
+        polyhedron_stencil(),
     ),
 });
 
@@ -43,49 +43,260 @@ gfx_pipeline!(z_fail_bounding_box_pipeline {
     ),
     mvp: gfx::Global<[[f32; 4]; 4]> = "u_mvp",
     color: gfx::Global<[f32; 4]> = "u_color",
+    highlight_color: gfx::Global<[f32; 4]> = "u_highlight_color",
+    highlight_pulse: gfx::Global<f32> = "u_highlight_pulse",
+    states: gfx::TextureSampler<[f32; 4]> = "t_states",
+    colors: gfx::TextureSampler<[f32; 4]> = "t_colors",
+    fades: gfx::TextureSampler<[f32; 4]> = "t_fades",
+    use_states: gfx::Global<i32> = "u_use_states",
+    time: gfx::Global<f32> = "u_time",
+    filter_by_time: gfx::Global<i32> = "u_filter_by_time",
+    validity: gfx::TextureSampler<[f32; 4]> = "t_validity",
+    multiply: gfx::Global<i32> = "u_multiply",
+    // Only declared by `FillShader`s.
+    fill_params: gfx::Global<[[f32; 4]; 4]> = "u_fill_params",
+    fill_texture: gfx::TextureSampler<[f32; 4]> = "t_fill",
+    fill_attributes: gfx::TextureSampler<[f32; 4]> = "t_fill_attributes",
     vertex_buffer: gfx::VertexBuffer<Vertex> = (),
     out_depth_stencil: gfx::DepthStencilTarget<gfx::format::DepthStencil> = (
         gfx::preset::depth::PASS_TEST,
-        gfx::state::Stencil::new(
-            // A fragment is only "inside" the polyhedron, and thus supposed to be drawn, if the
-            // stencil buffer is nonzero at that point.
-            gfx::state::Comparison::NotEqual,
-            255,
-            (
-                // An important property of the stencil is that it is all zeroes after this
-                // pipeline runs, so that the next draw doesn't need to clear the stencil first.
-                //
-                // If the stencil test fails, the value is zero and thus should be kept.
-                gfx::state::StencilOp::Keep,
-                // This never happens, because the depth test always passes.
-                gfx::state::StencilOp::Keep,
-                // The stencil test passed, so the value should be reset to zero.
-                gfx::state::StencilOp::Replace,
-            ),
-        ),
+        bounding_box_stencil(),
     ),
 });
 
+/// The stencil operations for drawing polyhedra, which leave the stencil buffer nonzero wherever
+/// a polyhedron encloses the terrain.
+pub(crate) fn polyhedron_stencil() -> gfx::state::Stencil {
+    stencil(Pass::Polyhedron.stencil())
+}
+
+/// The stencil operations for drawing bounding boxes, which draw wherever the stencil buffer is
+/// nonzero.
+pub(crate) fn bounding_box_stencil() -> gfx::state::Stencil {
+    stencil(Pass::Cover.stencil())
+}
+
+/// The rasterizer state for drawing `pass` over terrain drawn with the `depth` convention, culling
+/// the faces `Pass::state` says it does.
+pub(crate) fn rasterizer(pass: Pass, depth: DepthConvention) -> gfx::state::Rasterizer {
+    let cull_face = match pass.state(depth).cull_face {
+        CullFace::Nothing => gfx::state::CullFace::Nothing,
+        CullFace::Front => gfx::state::CullFace::Front,
+    };
+
+    gfx::state::Rasterizer {
+        cull_face: cull_face,
+        ..gfx::state::Rasterizer::new_fill()
+    }
+}
+
+fn stencil(state: StencilState) -> gfx::state::Stencil {
+    // The reference value isn't part of gfx's pipeline state, and is passed with each draw's
+    // data instead.
+    gfx::state::Stencil {
+        front: stencil_side(state.front),
+        back: stencil_side(state.back),
+    }
+}
+
+fn stencil_side(face: StencilFace) -> gfx::state::StencilSide {
+    gfx::state::StencilSide {
+        fun: comparison(face.test),
+        mask_read: 255,
+        mask_write: 255,
+        op_fail: stencil_op(face.op_fail),
+        op_depth_fail: stencil_op(face.op_depth_fail),
+        op_pass: stencil_op(face.op_pass),
+    }
+}
+
+fn comparison(comparison: Comparison) -> gfx::state::Comparison {
+    match comparison {
+        Comparison::Always => gfx::state::Comparison::Always,
+        Comparison::LessEqual => gfx::state::Comparison::LessEqual,
+        Comparison::GreaterEqual => gfx::state::Comparison::GreaterEqual,
+        Comparison::NotEqual => gfx::state::Comparison::NotEqual,
+    }
+}
+
+fn stencil_op(op: StencilOp) -> gfx::state::StencilOp {
+    match op {
+        StencilOp::Keep => gfx::state::StencilOp::Keep,
+        StencilOp::Replace => gfx::state::StencilOp::Replace,
+        StencilOp::IncrementWrap => gfx::state::StencilOp::IncrementWrap,
+        StencilOp::DecrementWrap => gfx::state::StencilOp::DecrementWrap,
+    }
+}
+
+/// A `DrawSink` that draws into a gfx `Encoder`, with one pipeline for each pass.
+pub(crate) struct EncoderSink<'a, R, C, P, B>
+where
+    R: gfx::Resources + 'a,
+    C: gfx::CommandBuffer<R> + 'a,
+    P: gfx::pso::PipelineData<R> + 'a,
+    B: gfx::pso::PipelineData<R> + 'a,
+{
+    pub encoder: &'a mut gfx::Encoder<R, C>,
+    pub indices: &'a RenderablePolygonIndices<R>,
+    pub polyhedron_pso: &'a gfx::pso::PipelineState<R, P::Meta>,
+    pub polyhedron_data: &'a P,
+    pub bounding_box_pso: &'a gfx::pso::PipelineState<R, B::Meta>,
+    pub bounding_box_data: &'a B,
+}
+
+impl<'a, R, C, P, B> DrawSink for EncoderSink<'a, R, C, P, B>
+where
+    R: gfx::Resources + 'a,
+    C: gfx::CommandBuffer<R> + 'a,
+    P: gfx::pso::PipelineData<R> + 'a,
+    B: gfx::pso::PipelineData<R> + 'a,
+{
+    fn draw(&mut self, command: DrawCommand) {
+        match command.pass {
+            Pass::Polyhedron => {
+                let buffer = &self.indices.polyhedron_buffer;
+                let slice = RenderablePolygonIndices::sub_slice(buffer, command.indices);
+                self.encoder
+                    .draw(&slice, self.polyhedron_pso, self.polyhedron_data);
+            }
+            Pass::Cover => {
+                let buffer = &self.indices.bounding_box_buffer;
+                let slice = RenderablePolygonIndices::sub_slice(buffer, command.indices);
+                self.encoder
+                    .draw(&slice, self.bounding_box_pso, self.bounding_box_data);
+            }
+        }
+    }
+}
+
 /// Drives graphics operations.
 ///
 /// This struct contains the shaders and stencil operations necessary to render draped polygons
 /// onto a terrain.
 #[derive(Clone, Debug)]
 pub struct DrapingRenderer<R: gfx::Resources> {
+    // Compiled once, and shared by the polyhedron pipelines of every kind of output.
+    pub(crate) polyhedron_shaders: Shaders<R>,
     polyhedron_pso: gfx::pso::PipelineState<R, z_fail_polyhedron_pipeline::Meta>,
-    bounding_box_pso: gfx::pso::PipelineState<R, z_fail_bounding_box_pipeline::Meta>,
+    // One per `BlendMode`, in declaration order.
+    bounding_box_psos: Vec<gfx::pso::PipelineState<R, z_fail_bounding_box_pipeline::Meta>>,
+    pub(crate) deferred: Option<DeferredPipelines<R>>,
+    pub(crate) mask: MaskPipelines<R>,
+    antialiasing: Antialiasing,
+    pub(crate) variant: ShaderVariant,
+    depth_bias: Option<gfx::state::Offset>,
+    // Bound in place of a `PolygonStates` when rendering without one, since the shaders always
+    // declare the state texture.
+    no_states: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    no_colors: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    no_fades: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    pub(crate) sampler: gfx::handle::Sampler<R>,
 }
 
 impl<R: gfx::Resources> DrapingRenderer<R> {
-    /// Set up the pipeline state objects needed for rendering draped polygons.
-    pub fn new<F: gfx::Factory<R>>(factory: &mut F) -> DrapingRenderer<R> {
-        DrapingRenderer {
-            polyhedron_pso: Self::polyhedron_pso(factory),
-            bounding_box_pso: Self::bounding_box_pso(factory),
-        }
+    /// Set up the pipeline state objects needed for rendering draped polygons onto terrain drawn
+    /// with the `depth` convention.
+    ///
+    /// The shaders are compiled as GLSL 1.50 if `factory` accepts it, and otherwise as GLSL ES
+    /// 3.00, so the same code runs on desktop OpenGL, OpenGL ES and WebGL 2.
+    ///
+    /// Panics if the shaders can't be compiled or the pipelines created; see `try_new`.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, depth: DepthConvention) -> DrapingRenderer<R> {
+        Self::with_antialiasing(factory, depth, Antialiasing::none())
+    }
+
+    /// Like `new`, but returns an error rather than panicking if the renderer's shaders or
+    /// pipelines can't be created, such as on a driver that supports neither shader language, so
+    /// that the application can fall back to another way of drawing polygons.
+    pub fn try_new<F: gfx::Factory<R>>(
+        factory: &mut F,
+        depth: DepthConvention,
+    ) -> Result<DrapingRenderer<R>, DrapingError> {
+        Self::try_with_antialiasing(factory, depth, Antialiasing::none())
     }
 
-    /// Render polygons in `buffer` using `indices` to choose the polygons.
+    /// Set up a renderer which smooths the edges of polygons as described by `antialiasing`.
+    ///
+    /// Panics if the shaders can't be compiled or the pipelines created; see
+    /// `try_with_antialiasing`.
+    pub fn with_antialiasing<F: gfx::Factory<R>>(
+        factory: &mut F,
+        depth: DepthConvention,
+        antialiasing: Antialiasing,
+    ) -> DrapingRenderer<R> {
+        Self::try_with_antialiasing(factory, depth, antialiasing)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `with_antialiasing`, but returns an error rather than panicking if the renderer's
+    /// shaders or pipelines can't be created.
+    pub fn try_with_antialiasing<F: gfx::Factory<R>>(
+        factory: &mut F,
+        depth: DepthConvention,
+        antialiasing: Antialiasing,
+    ) -> Result<DrapingRenderer<R>, DrapingError> {
+        let variant = ShaderVariant::detect(factory, depth);
+        let polyhedron_shaders =
+            variant.compile(factory, "polyhedron", POLYHEDRON_VERT, POLYHEDRON_FRAG, &[])?;
+        let bounding_box_shaders = variant.compile(
+            factory,
+            "bounding_box",
+            BOUNDING_BOX_VERT,
+            BOUNDING_BOX_FRAG,
+            &[],
+        )?;
+        let bounding_box_psos = BlendMode::all()
+            .iter()
+            .map(|&blend| {
+                Self::bounding_box_pso(factory, &bounding_box_shaders, depth, blend, antialiasing)
+            })
+            .collect::<Result<_, _>>()?;
+
+        let no_states = Self::placeholder::<_, gfx::format::Rgba8>(
+            factory,
+            "t_states",
+            [255, 0, 255, 0],
+        )?;
+        let no_colors = Self::placeholder::<_, gfx::format::Rgba8>(
+            factory,
+            "t_colors",
+            [255, 255, 255, 255],
+        )?;
+        let no_fades = Self::placeholder::<_, gfx::format::Rgba32F>(
+            factory,
+            "t_fades",
+            [0, 0, 1.0f32.to_bits(), 0],
+        )?;
+
+        // Per-polygon textures are only read with `texelFetch`, so filtering doesn't matter.
+        let sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
+            gfx::texture::FilterMethod::Scale,
+            gfx::texture::WrapMode::Clamp,
+        ));
+
+        Ok(DrapingRenderer {
+            polyhedron_pso: Self::polyhedron_pso(
+                factory,
+                &polyhedron_shaders,
+                variant,
+                antialiasing,
+                None,
+            )?,
+            bounding_box_psos: bounding_box_psos,
+            deferred: None,
+            mask: MaskPipelines::new(factory, variant, &polyhedron_shaders)?,
+            polyhedron_shaders: polyhedron_shaders,
+            antialiasing: antialiasing,
+            variant: variant,
+            depth_bias: None,
+            no_states: no_states,
+            no_colors: no_colors,
+            no_fades: no_fades,
+            sampler: sampler,
+        })
+    }
+
+    /// Render the polygons in `params`, combining them with `render_target` using `blend`.
     ///
     /// The depth buffer in `depth_stencil_target` should contain the depth values of your terrain
     /// -- in other words, draw your terrain just before you call this function, and make sure you
@@ -95,11 +306,12 @@ impl<R: gfx::Resources> DrapingRenderer<R> {
     /// stencil buffer is guaranteed to remain zero after each call, so there is no need to clear
     /// the stencil buffer between calls to this function.
     ///
-    /// `mvp` should be a model-view-projection matrix. *You probably want to use a different `mvp`
-    /// than what you're normally using.* The polygons will only render onto terrain with z-values
-    /// between 0 and 1; you should apply a transformation to alter these z-bounds. For example, if
-    /// your terrain is bounded in height between `z_min` and `z_max`, your mvp use you from this
-    /// library might be constructed as:
+    /// `params.mvp` should be a model-view-projection matrix. *You probably want to use a
+    /// different `mvp` than what you're normally using.* The polygons will only render onto
+    /// terrain with z-values between 0 and 1; you should apply a transformation to alter these
+    /// z-bounds. For example, if your terrain is bounded in height between `z_min` and `z_max`,
+    /// your mvp use you from this library might be constructed as follows. (If the buffer was
+    /// built with a different up-axis, scale along that axis instead.)
     ///
     /// ```rust,compile_fail
     /// // With the relevant imports, this is working code when using the `cgmath` crate.
@@ -108,137 +320,1052 @@ impl<R: gfx::Resources> DrapingRenderer<R> {
     /// let stretch_z = Matrix4::from_nonuniform_scale(1.0, 1.0, max_z - min_z);
     /// let draping_mvp = usual_mvp * translate_z * stretch_z;
     /// ```
+    ///
+    /// Every polygon in `params.indices` is drawn, even if it is off-screen. If you are only
+    /// looking at a small part of your polygons, consider using `indices.cull(mvp)` instead.
+    ///
+    /// With `RenderParams::with_time`, only polygons whose validity interval (see
+    /// `Polygon::with_validity`) contains the time are drawn. Polygons are hidden in the vertex
+    /// shaders, so changing the time from frame to frame is free. With
+    /// `RenderParams::with_states`, individual polygons are hidden, highlighted, faded and
+    /// colored according to the states.
     pub fn render<C: gfx::CommandBuffer<R>>(
         &self,
         encoder: &mut gfx::Encoder<R, C>,
         render_target: gfx::handle::RenderTargetView<R, gfx::format::Srgba8>,
         depth_stencil_target: gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
-        mvp: [[f32; 4]; 4],
-        color: [f32; 4],
-        buffer: &RenderablePolygonBuffer<R>,
-        indices: &RenderablePolygonIndices<R>,
+        blend: BlendMode,
+        params: RenderParams<R>,
     ) {
-        let polyhedron_bundle = gfx::Bundle {
-            pso: self.polyhedron_pso.clone(),
-            slice: indices.polyhedron_slice.clone(),
-            data: z_fail_polyhedron_pipeline::Data {
-                mvp: mvp,
-                out_color: render_target.clone(),
-                out_depth_stencil: (depth_stencil_target.clone(), (0, 0)),
-                vertex_buffer: buffer.polyhedron_vertex_buffer.clone(),
-            },
-        };
+        self.draw(encoder, render_target, depth_stencil_target, blend, None, params)
+    }
 
-        let bounding_box_bundle = gfx::Bundle {
-            pso: self.bounding_box_pso.clone(),
-            slice: indices.bounding_box_slice.clone(),
-            data: z_fail_bounding_box_pipeline::Data {
-                color: color,
-                mvp: mvp,
-                out_color: render_target.clone(),
-                out_depth_stencil: (depth_stencil_target.clone(), (0, 0)),
-                vertex_buffer: buffer.bounding_box_vertex_buffer.clone(),
-            },
-        };
+    /// How this renderer smooths the edges of polygons.
+    pub fn antialiasing(&self) -> Antialiasing {
+        self.antialiasing
+    }
 
-        polyhedron_bundle.encode(encoder);
-        bounding_box_bundle.encode(encoder);
+    /// The depth convention of the terrain this renderer drapes polygons onto.
+    pub fn depth_convention(&self) -> DepthConvention {
+        self.variant.depth
     }
 
-    fn polyhedron_pso<F: gfx::Factory<R>>(
+    /// The language this renderer's shaders were compiled as.
+    pub fn shader_language(&self) -> ShaderLanguage {
+        self.variant.language
+    }
+
+    /// The offset applied to the depth of shadow volumes, if any.
+    pub fn depth_bias(&self) -> Option<gfx::state::Offset> {
+        self.depth_bias
+    }
+
+    /// Offset the depth of shadow volumes before they are tested against the terrain, by a
+    /// constant number of depth buffer units plus a factor of their depth slope.
+    ///
+    /// Where terrain touches the top or bottom of a shadow volume, the two fight over which is in
+    /// front, leaving speckled holes in the polygon. `PolygonBuffer` pads shadow volumes slightly
+    /// to avoid this, but with a coarse depth buffer, or terrain beyond the padding, a bias may
+    /// still be needed. Pulling the shadow volumes towards the camera, i.e. negative offsets with
+    /// `DepthConvention::Standard` and positive ones with `DepthConvention::Reversed`, keeps them
+    /// from hiding behind terrain they touch.
+    ///
+    /// This rebuilds the renderer's polyhedron pipelines. If that fails, the error is returned
+    /// and the renderer is left unchanged.
+    pub fn set_depth_bias<F: gfx::Factory<R>>(
+        &mut self,
         factory: &mut F,
-    ) -> gfx::pso::PipelineState<R, z_fail_polyhedron_pipeline::Meta> {
-        let shaders = factory
-            .create_shader_set(
-                include_bytes!("shaders/polyhedron.vert"),
-                include_bytes!("shaders/polyhedron.frag"),
+        depth_bias: Option<gfx::state::Offset>,
+    ) -> Result<(), DrapingError> {
+        let shaders = &self.polyhedron_shaders;
+        let polyhedron_pso =
+            Self::polyhedron_pso(factory, shaders, self.variant, self.antialiasing, depth_bias)?;
+        let mut mask = self.mask.clone();
+        mask.set_depth_bias(factory, shaders, self.variant, depth_bias)?;
+        let mut deferred = self.deferred.clone();
+        if let Some(ref mut deferred) = deferred {
+            deferred.set_depth_bias(factory, shaders, self.variant, depth_bias)?;
+        }
+
+        self.depth_bias = depth_bias;
+        self.polyhedron_pso = polyhedron_pso;
+        self.mask = mask;
+        self.deferred = deferred;
+        Ok(())
+    }
+
+    /// Render the fringes along the edges of polygons, which soften their edges.
+    ///
+    /// `params.indices` should contain the `Polygon::edge_fade` of each polygon in the fill, and
+    /// is drawn in `params.color` with its alpha scaled by this renderer's
+    /// `Antialiasing::edge_fade`, filtered by the time, if any. The states, if any, aren't used,
+    /// since they are laid out for the fill's polygons. Call this just after rendering the fill;
+    /// if the renderer has no edge fade, it draws nothing.
+    pub fn render_edge_fade<C: gfx::CommandBuffer<R>>(
+        &self,
+        encoder: &mut gfx::Encoder<R, C>,
+        render_target: gfx::handle::RenderTargetView<R, gfx::format::Srgba8>,
+        depth_stencil_target: gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        params: RenderParams<R>,
+    ) {
+        if let Some(color) = self.edge_fade_color(params.color) {
+            let params = RenderParams {
+                color: color,
+                states: None,
+                ..params
+            };
+            self.draw(
+                encoder,
+                render_target,
+                depth_stencil_target,
+                BlendMode::Alpha,
+                None,
+                params,
             )
-            .unwrap();
+        }
+    }
+
+    /// The color to draw the edge fade of polygons drawn in `color` in, if there is an edge fade.
+    pub(crate) fn edge_fade_color(&self, color: [f32; 4]) -> Option<[f32; 4]> {
+        self.antialiasing
+            .edge_fade
+            .map(|opacity| [color[0], color[1], color[2], color[3] * opacity])
+    }
+
+    /// Draw `params` with `fill`, or the built-in fill without one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fill` was created for another renderer.
+    pub(crate) fn draw<C: gfx::CommandBuffer<R>>(
+        &self,
+        encoder: &mut gfx::Encoder<R, C>,
+        render_target: gfx::handle::RenderTargetView<R, gfx::format::Srgba8>,
+        depth_stencil_target: gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        blend: BlendMode,
+        fill: Option<&FillShader<R>>,
+        params: RenderParams<R>,
+    ) {
+        let RenderParams {
+            mvp,
+            color,
+            buffer,
+            indices,
+            states,
+            time,
+        } = params;
+        let state_params = self.state_params(states, color);
+        let use_states = states.is_some() as i32;
+        let filter_by_time = time.is_some() as i32;
+        let time = time.unwrap_or(0.0);
+
+        let (bounding_box_pso, fill_params, fill_texture, fill_attributes) = match fill {
+            Some(fill) => {
+                assert!(
+                    fill.variant == self.variant && fill.antialiasing == self.antialiasing,
+                    "this fill shader was created for another renderer"
+                );
+
+                (
+                    &fill.bounding_box_psos[blend as usize],
+                    fill.params(),
+                    fill.texture
+                        .clone()
+                        .unwrap_or_else(|| (self.no_states.clone(), self.sampler.clone())),
+                    fill.attributes_view(),
+                )
+            }
+            None => (
+                &self.bounding_box_psos[blend as usize],
+                [[0.0; 4]; 4],
+                (self.no_states.clone(), self.sampler.clone()),
+                self.no_fades.clone(),
+            ),
+        };
+
+        let polyhedron_data = z_fail_polyhedron_pipeline::Data {
+            mvp: mvp,
+            states: (state_params.states.clone(), self.sampler.clone()),
+            use_states: use_states,
+            time: time,
+            filter_by_time: filter_by_time,
+            validity: (buffer.validity_view.clone(), self.sampler.clone()),
+            out_color: render_target.clone(),
+            out_depth_stencil: (depth_stencil_target.clone(), (0, 0)),
+            vertex_buffer: buffer.polyhedron_vertex_buffer.clone(),
+        };
+
+        let bounding_box_data = z_fail_bounding_box_pipeline::Data {
+            color: color,
+            highlight_color: state_params.highlight_color,
+            highlight_pulse: state_params.highlight_pulse,
+            states: (state_params.states, self.sampler.clone()),
+            colors: (state_params.colors, self.sampler.clone()),
+            fades: (state_params.fades, self.sampler.clone()),
+            use_states: use_states,
+            time: time,
+            filter_by_time: filter_by_time,
+            validity: (buffer.validity_view.clone(), self.sampler.clone()),
+            multiply: (blend == BlendMode::Multiply) as i32,
+            fill_params: fill_params,
+            fill_texture: fill_texture,
+            fill_attributes: (fill_attributes, self.sampler.clone()),
+            mvp: mvp,
+            out_color: render_target.clone(),
+            out_depth_stencil: (depth_stencil_target.clone(), (0, 0)),
+            vertex_buffer: buffer.bounding_box_vertex_buffer.clone(),
+        };
+
+        let mut sink = EncoderSink {
+            encoder: encoder,
+            indices: indices,
+            polyhedron_pso: &self.polyhedron_pso,
+            polyhedron_data: &polyhedron_data,
+            bounding_box_pso: bounding_box_pso,
+            bounding_box_data: &bounding_box_data,
+        };
+
+        // Polygons drawn differently from one another are drawn one at a time, so that where
+        // their bounding boxes overlap, each pixel is drawn by the polygon it lies in rather than
+        // by the first bounding box to reach it.
+        if states.is_some() || fill.is_some() {
+            indices.draw_commands_per_polygon(&mut sink);
+        } else {
+            indices.draw_commands(&mut sink);
+        }
+    }
 
-        let rasterizer = gfx::state::Rasterizer::new_fill();
+    /// The state texture views, highlight color and highlight pulse to draw `states` with.
+    pub(crate) fn state_params(
+        &self,
+        states: Option<&PolygonStates<R>>,
+        color: [f32; 4],
+    ) -> StateParams<R> {
+        match states {
+            Some(states) => StateParams {
+                states: states.view.clone(),
+                colors: states.color_view.clone(),
+                fades: states.fade_view.clone(),
+                highlight_color: states.highlight_color(),
+                highlight_pulse: states.highlight_pulse().unwrap_or(0.0),
+            },
+            None => StateParams {
+                states: self.no_states.clone(),
+                colors: self.no_colors.clone(),
+                fades: self.no_fades.clone(),
+                highlight_color: color,
+                highlight_pulse: 0.0,
+            },
+        }
+    }
 
+    /// Create a 1x1 texture holding `texel`, to bind to the sampler `name` when there is nothing
+    /// else to bind to it.
+    fn placeholder<F, T>(
+        factory: &mut F,
+        name: &str,
+        texel: <T::Surface as gfx::format::SurfaceTyped>::DataType,
+    ) -> Result<gfx::handle::ShaderResourceView<R, T::View>, DrapingError>
+    where
+        F: gfx::Factory<R>,
+        T: gfx::format::TextureFormat,
+    {
         factory
-            .create_pipeline_state(
-                &shaders,
-                gfx::Primitive::TriangleList,
-                rasterizer,
-                z_fail_polyhedron_pipeline::new(),
+            .create_texture_immutable::<T>(
+                gfx::texture::Kind::D2(1, 1, gfx::texture::AaMode::Single),
+                gfx::texture::Mipmap::Provided,
+                &[&[texel]],
             )
-            .unwrap()
+            .map(|(_, view)| view)
+            .map_err(|error| DrapingError::Texture {
+                name: name.to_owned(),
+                error: error,
+            })
     }
 
-    fn bounding_box_pso<F: gfx::Factory<R>>(
+    fn polyhedron_pso<F: gfx::Factory<R>>(
         factory: &mut F,
-    ) -> gfx::pso::PipelineState<R, z_fail_bounding_box_pipeline::Meta> {
-        let shaders = factory
-            .create_shader_set(
-                include_bytes!("shaders/bounding_box.vert"),
-                include_bytes!("shaders/bounding_box.frag"),
-            )
-            .unwrap();
+        shaders: &Shaders<R>,
+        variant: ShaderVariant,
+        antialiasing: Antialiasing,
+        depth_bias: Option<gfx::state::Offset>,
+    ) -> Result<gfx::pso::PipelineState<R, z_fail_polyhedron_pipeline::Meta>, DrapingError> {
+        let rasterizer = gfx::state::Rasterizer {
+            offset: depth_bias,
+            samples: antialiasing.samples(),
+            ..rasterizer(Pass::Polyhedron, variant.depth)
+        };
 
+        shaders.pipeline(
+            factory,
+            rasterizer,
+            z_fail_polyhedron_pipeline::Init {
+                out_depth_stencil: (variant.depth.polyhedron_test(), polyhedron_stencil()),
+                ..z_fail_polyhedron_pipeline::new()
+            },
+        )
+    }
+
+    pub(crate) fn bounding_box_pso<F: gfx::Factory<R>>(
+        factory: &mut F,
+        shaders: &Shaders<R>,
+        depth: DepthConvention,
+        blend: BlendMode,
+        antialiasing: Antialiasing,
+    ) -> Result<gfx::pso::PipelineState<R, z_fail_bounding_box_pipeline::Meta>, DrapingError> {
         let rasterizer = gfx::state::Rasterizer {
-            cull_face: gfx::state::CullFace::Front,
-            ..gfx::state::Rasterizer::new_fill()
+            samples: antialiasing.samples(),
+            ..rasterizer(Pass::Cover, depth)
         };
 
-        factory
-            .create_pipeline_state(
-                &shaders,
-                gfx::Primitive::TriangleList,
-                rasterizer,
-                z_fail_bounding_box_pipeline::new(),
-            )
-            .unwrap()
+        let init = z_fail_bounding_box_pipeline::Init {
+            out_color: ("o_color", gfx::state::ColorMask::all(), blend.state()),
+            ..z_fail_bounding_box_pipeline::new()
+        };
+
+        shaders.pipeline(factory, rasterizer, init)
+    }
+}
+
+/// What the shaders need to draw a `PolygonStates`, or placeholders for drawing without one.
+pub(crate) struct StateParams<R: gfx::Resources> {
+    pub states: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    pub colors: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    pub fades: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    pub highlight_color: [f32; 4],
+    pub highlight_pulse: f32,
+}
+
+/// The polygons to draw, and how to color them, for `DrapingRenderer::render` and its variants,
+/// such as `render_with_fill`, `render_mask` and `render_deferred`.
+///
+/// Create them with `new`, then add `PolygonStates` or a time with `with_states` and
+/// `with_time`. Without `time`, polygons are drawn regardless of their validity interval.
+#[derive(Debug)]
+pub struct RenderParams<'a, R: gfx::Resources + 'a> {
+    /// The model-view-projection matrix, as for `DrapingRenderer::render`.
+    pub mvp: [[f32; 4]; 4],
+    /// The color polygons without a color of their own are filled with.
+    pub color: [f32; 4],
+    /// The buffer `indices` index into.
+    pub buffer: &'a RenderablePolygonBuffer<R>,
+    /// The polygons to draw.
+    pub indices: &'a RenderablePolygonIndices<R>,
+    /// The states to hide, highlight, fade and color polygons by, if any.
+    pub states: Option<&'a PolygonStates<R>>,
+    /// The current time, to filter polygons by their validity interval and drive the animations
+    /// of `states`, if any.
+    pub time: Option<f32>,
+}
+
+impl<'a, R: gfx::Resources> RenderParams<'a, R> {
+    /// Draw `indices` from `buffer` in `color`, without states, and regardless of time.
+    pub fn new(
+        mvp: [[f32; 4]; 4],
+        color: [f32; 4],
+        buffer: &'a RenderablePolygonBuffer<R>,
+        indices: &'a RenderablePolygonIndices<R>,
+    ) -> RenderParams<'a, R> {
+        RenderParams {
+            mvp: mvp,
+            color: color,
+            buffer: buffer,
+            indices: indices,
+            states: None,
+            time: None,
+        }
+    }
+
+    /// Hide, highlight, fade and color polygons according to `states`, which should have been
+    /// created for the buffer and had its changes uploaded with `PolygonStates::upload`.
+    pub fn with_states(mut self, states: &'a PolygonStates<R>) -> RenderParams<'a, R> {
+        self.states = Some(states);
+        self
+    }
+
+    /// Only draw polygons whose validity interval contains `time`, which also drives the
+    /// animations of the states, if any.
+    pub fn with_time(mut self, time: f32) -> RenderParams<'a, R> {
+        self.time = Some(time);
+        self
+    }
+}
+
+// Derived impls would require `R: Copy`.
+impl<'a, R: gfx::Resources> Clone for RenderParams<'a, R> {
+    fn clone(&self) -> RenderParams<'a, R> {
+        *self
     }
 }
 
+impl<'a, R: gfx::Resources> Copy for RenderParams<'a, R> {}
+
 /// A set of vertex buffer handles ready for rendering.
 #[derive(Clone, Debug)]
 pub struct RenderablePolygonBuffer<R: gfx::Resources> {
-    polyhedron_vertex_buffer: gfx::handle::Buffer<R, Vertex>,
-    bounding_box_vertex_buffer: gfx::handle::Buffer<R, Vertex>,
+    pub(crate) polyhedron_vertex_buffer: gfx::handle::Buffer<R, Vertex>,
+    pub(crate) bounding_box_vertex_buffer: gfx::handle::Buffer<R, Vertex>,
+    // One texel per polygon, laid out like a `PolygonStates`: the start and end of the polygon's
+    // validity interval, in the red and green channels.
+    pub(crate) validity_view: gfx::handle::ShaderResourceView<R, [f32; 4]>,
 }
 
 impl<R: gfx::Resources> RenderablePolygonBuffer<R> {
     /// Prepare a `PolygonBuffer` for rendering.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer has too many polygons for their validity intervals to fit in a
+    /// texture, i.e. more than 64 million, or if its vertex buffers or validity texture can't be
+    /// created; see `try_new`.
     pub fn new<F: gfx::Factory<R>>(
         factory: &mut F,
         buffer: &PolygonBuffer,
     ) -> RenderablePolygonBuffer<R> {
-        RenderablePolygonBuffer {
-            polyhedron_vertex_buffer: factory.create_vertex_buffer(&buffer.polyhedron_vertices),
-            bounding_box_vertex_buffer: factory.create_vertex_buffer(&buffer.bounding_box_vertices),
-        }
+        Self::try_new(factory, buffer).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `new`, but returns an error rather than panicking if the vertex buffers or validity
+    /// texture can't be created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer has too many polygons for their validity intervals to fit in a
+    /// texture, i.e. more than 64 million.
+    pub fn try_new<F: gfx::Factory<R>>(
+        factory: &mut F,
+        buffer: &PolygonBuffer,
+    ) -> Result<RenderablePolygonBuffer<R>, DrapingError> {
+        Self::from_vertices(
+            factory,
+            &buffer.polyhedron_vertices,
+            &buffer.bounding_box_vertices,
+            &buffer.validities,
+        )
+    }
+
+    pub(crate) fn from_vertices<F: gfx::Factory<R>>(
+        factory: &mut F,
+        polyhedron_vertices: &[Vertex],
+        bounding_box_vertices: &[Vertex],
+        validities: &[[f32; 2]],
+    ) -> Result<RenderablePolygonBuffer<R>, DrapingError> {
+        let (width, height) = texture_size(validities.len());
+        let always = [::std::f32::MIN, ::std::f32::MAX];
+        let texels: Vec<_> = (0..width * height)
+            .map(|index| {
+                let validity = validities.get(index).unwrap_or(&always);
+                [validity[0].to_bits(), validity[1].to_bits(), 0, 0]
+            })
+            .collect();
+
+        let (_, validity_view) = factory
+            .create_texture_immutable::<gfx::format::Rgba32F>(
+                gfx::texture::Kind::D2(width as u16, height as u16, gfx::texture::AaMode::Single),
+                gfx::texture::Mipmap::Provided,
+                &[&texels],
+            )
+            .map_err(|error| DrapingError::Texture {
+                name: "t_validity".to_owned(),
+                error: error,
+            })?;
+
+        let polyhedron_vertex_buffer =
+            Self::vertex_buffer(factory, "polyhedra", polyhedron_vertices)?;
+        let bounding_box_vertex_buffer =
+            Self::vertex_buffer(factory, "bounding_boxes", bounding_box_vertices)?;
+
+        Ok(RenderablePolygonBuffer {
+            polyhedron_vertex_buffer: polyhedron_vertex_buffer,
+            bounding_box_vertex_buffer: bounding_box_vertex_buffer,
+            validity_view: validity_view,
+        })
+    }
+
+    fn vertex_buffer<F: gfx::Factory<R>>(
+        factory: &mut F,
+        name: &str,
+        vertices: &[Vertex],
+    ) -> Result<gfx::handle::Buffer<R, Vertex>, DrapingError> {
+        let (role, bind) = (gfx::buffer::Role::Vertex, gfx::memory::Bind::empty());
+        factory
+            .create_buffer_immutable(vertices, role, bind)
+            .map_err(|error| DrapingError::Buffer {
+                name: name.to_owned(),
+                error: error,
+            })
     }
 }
 
 /// A set of index buffer handles ready for rendering.
 #[derive(Clone, Debug)]
 pub struct RenderablePolygonIndices<R: gfx::Resources> {
-    polyhedron_slice: gfx::Slice<R>,
-    bounding_box_slice: gfx::Slice<R>,
+    // The whole index buffers, which indices culled from these share, even if they draw nothing.
+    polyhedron_buffer: gfx::IndexBuffer<R>,
+    bounding_box_buffer: gfx::IndexBuffer<R>,
+    // The parts of the index buffers to draw.
+    polyhedron_ranges: Vec<Range<u32>>,
+    bounding_box_ranges: Vec<Range<u32>>,
+    polygons: Vec<PolygonRange>,
 }
 
 impl<R: gfx::Resources> RenderablePolygonIndices<R> {
     /// Prepare a `PolygonBufferIndices` for rendering.
+    ///
+    /// The index buffers use `IndexFormat::U16` if all the indices fit in 16 bits, which is the
+    /// case whenever the underlying `PolygonBuffer` is small enough, and `IndexFormat::U32`
+    /// otherwise.
     pub fn new<F: gfx::Factory<R>>(
         factory: &mut F,
         indices: &PolygonBufferIndices,
     ) -> RenderablePolygonIndices<R> {
+        Self::from_slices(
+            factory,
+            &indices.polyhedron_indices,
+            &indices.bounding_box_indices,
+            indices.polygons.clone(),
+        )
+    }
+
+    /// Prepare a `PolygonBufferIndices` for rendering, using a specific index format.
+    ///
+    /// Returns an error if `format` is `IndexFormat::U16` but some index does not fit in 16 bits.
+    pub fn with_format<F: gfx::Factory<R>>(
+        factory: &mut F,
+        indices: &PolygonBufferIndices,
+        format: IndexFormat,
+    ) -> Result<RenderablePolygonIndices<R>, IndexOverflowError> {
+        Self::from_slices_with_format(
+            factory,
+            &indices.polyhedron_indices,
+            &indices.bounding_box_indices,
+            indices.polygons.clone(),
+            format,
+        )
+    }
+
+    pub(crate) fn from_slices<F: gfx::Factory<R>>(
+        factory: &mut F,
+        polyhedron_indices: &[u32],
+        bounding_box_indices: &[u32],
+        polygons: Vec<PolygonRange>,
+    ) -> RenderablePolygonIndices<R> {
+        let max_index = Self::max_index(polyhedron_indices, bounding_box_indices);
+        let format = if max_index <= u16::max_value() as u32 {
+            IndexFormat::U16
+        } else {
+            IndexFormat::U32
+        };
+
+        // This can't fail, because the format was chosen to fit the indices.
+        Self::from_slices_with_format(
+            factory,
+            polyhedron_indices,
+            bounding_box_indices,
+            polygons,
+            format,
+        ).unwrap()
+    }
+
+    fn from_slices_with_format<F: gfx::Factory<R>>(
+        factory: &mut F,
+        polyhedron_indices: &[u32],
+        bounding_box_indices: &[u32],
+        polygons: Vec<PolygonRange>,
+        format: IndexFormat,
+    ) -> Result<RenderablePolygonIndices<R>, IndexOverflowError> {
+        let max_index = Self::max_index(polyhedron_indices, bounding_box_indices);
+        if format == IndexFormat::U16 && max_index > u16::max_value() as u32 {
+            return Err(IndexOverflowError { index: max_index });
+        }
+
+        Ok(RenderablePolygonIndices {
+            polyhedron_buffer: Self::create_buffer(factory, polyhedron_indices, format),
+            bounding_box_buffer: Self::create_buffer(factory, bounding_box_indices, format),
+            polyhedron_ranges: iter::once(0..polyhedron_indices.len() as u32).collect(),
+            bounding_box_ranges: iter::once(0..bounding_box_indices.len() as u32).collect(),
+            polygons: polygons,
+        })
+    }
+
+    /// Get the subset of these indices whose polygons may be visible through `mvp`.
+    ///
+    /// `mvp` should be the same matrix you will pass to `DrapingRenderer::render`. Each
+    /// polygon's bounding-box prism is tested against the view frustum on the CPU, and the
+    /// returned indices only draw the polygons that pass. The index buffers are shared with
+    /// `self`, so this is cheap enough to call every frame.
+    pub fn cull(&self, mvp: [[f32; 4]; 4]) -> RenderablePolygonIndices<R> {
+        let frustum = Frustum::from_mvp(mvp);
+        let polygons: Vec<_> = self.polygons
+            .iter()
+            .filter(|polygon| frustum.intersects_box(polygon.bounds[0], polygon.bounds[1]))
+            .cloned()
+            .collect();
+
+        // Adjacent visible polygons are merged into a single draw call.
+        let mut polyhedron_ranges: Vec<Range<u32>> = Vec::new();
+        let mut bounding_box_ranges: Vec<Range<u32>> = Vec::new();
+        for polygon in &polygons {
+            Self::push_range(&mut polyhedron_ranges, &polygon.polyhedron_indices);
+            Self::push_range(&mut bounding_box_ranges, &polygon.bounding_box_indices);
+        }
+
         RenderablePolygonIndices {
-            polyhedron_slice: Self::create_slice(factory, &indices.polyhedron_indices),
-            bounding_box_slice: Self::create_slice(factory, &indices.bounding_box_indices),
+            polyhedron_buffer: self.polyhedron_buffer.clone(),
+            bounding_box_buffer: self.bounding_box_buffer.clone(),
+            polyhedron_ranges: polyhedron_ranges,
+            bounding_box_ranges: bounding_box_ranges,
+            polygons: polygons,
         }
     }
 
-    fn create_slice<F: gfx::Factory<R>>(factory: &mut F, indices: &[u32]) -> gfx::Slice<R> {
+    /// Pass `sink` the commands that render these indices, as `DrapingRenderer::render` does
+    /// without states or a fill shader.
+    pub fn draw_commands<S: DrawSink>(&self, sink: &mut S) {
+        z_fail_commands(sink, &self.polyhedron_ranges, &self.bounding_box_ranges);
+    }
+
+    /// Pass `sink` the commands that render these indices one polygon at a time, as
+    /// `DrapingRenderer::render` does with states or a fill shader. See
+    /// `z_fail_commands_per_polygon`.
+    pub fn draw_commands_per_polygon<S: DrawSink>(&self, sink: &mut S) {
+        let ranges = self.polygons.iter().map(|polygon| {
+            (
+                polygon.polyhedron_indices.clone(),
+                polygon.bounding_box_indices.clone(),
+            )
+        });
+        z_fail_commands_per_polygon(sink, ranges);
+    }
+
+    fn create_buffer<F: gfx::Factory<R>>(
+        factory: &mut F,
+        indices: &[u32],
+        format: IndexFormat,
+    ) -> gfx::IndexBuffer<R> {
+        match format {
+            IndexFormat::U16 => {
+                let indices: Vec<u16> = indices.iter().map(|&i| i as u16).collect();
+                factory.create_index_buffer(indices.as_slice())
+            }
+            IndexFormat::U32 => factory.create_index_buffer(indices),
+        }
+    }
+
+    fn max_index(polyhedron_indices: &[u32], bounding_box_indices: &[u32]) -> u32 {
+        let polyhedron_max = polyhedron_indices.iter().max();
+        let bounding_box_max = bounding_box_indices.iter().max();
+        *polyhedron_max.max(bounding_box_max).unwrap_or(&0)
+    }
+
+    pub(crate) fn sub_slice(buffer: &gfx::IndexBuffer<R>, range: Range<u32>) -> gfx::Slice<R> {
         gfx::Slice {
-            start: 0,
-            end: indices.len() as u32,
+            start: range.start,
+            end: range.end,
             base_vertex: 0,
             instances: None,
-            buffer: factory.create_index_buffer(indices),
+            buffer: buffer.clone(),
+        }
+    }
+
+    fn push_range(ranges: &mut Vec<Range<u32>>, range: &Range<u32>) {
+        if let Some(last) = ranges.last_mut() {
+            if last.end == range.start {
+                last.end = range.end;
+                return;
+            }
+        }
+
+        ranges.push(range.clone());
+    }
+}
+
+/// How draped polygons are combined with the colors already in the render target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Mix the polygon's color over the terrain according to its alpha.
+    Alpha,
+    /// Add the polygon's color, scaled by its alpha, to the terrain.
+    Additive,
+    /// Multiply the terrain by the polygon's color. An alpha below 1 fades the color towards
+    /// white, which leaves the terrain unchanged.
+    Multiply,
+    /// Overwrite the terrain with the polygon's color, including its alpha.
+    Replace,
+}
+
+impl BlendMode {
+    pub(crate) fn all() -> [BlendMode; 4] {
+        [
+            BlendMode::Alpha,
+            BlendMode::Additive,
+            BlendMode::Multiply,
+            BlendMode::Replace,
+        ]
+    }
+
+    pub(crate) fn state(&self) -> gfx::state::Blend {
+        use gfx::state::{Blend, BlendValue, Equation, Factor};
+
+        match *self {
+            BlendMode::Alpha => gfx::preset::blend::ALPHA,
+            BlendMode::Additive => Blend::new(
+                Equation::Add,
+                Factor::ZeroPlus(BlendValue::SourceAlpha),
+                Factor::One,
+            ),
+            BlendMode::Multiply => gfx::preset::blend::MULTIPLY,
+            BlendMode::Replace => gfx::preset::blend::REPLACE,
+        }
+    }
+}
+
+/// How depth values are laid out in the depth buffer the terrain is drawn into.
+///
+/// With either convention, the projection may have an infinite far plane. With a finite far plane,
+/// shadow volumes extending beyond it are pulled onto it in the vertex shaders, much like depth
+/// clamping, since z-fail stenciling needs their far sides intact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthConvention {
+    /// Depth increases with distance, from 0 or -1 at the near plane to 1 at the far plane, and
+    /// the depth buffer is cleared to 1.
+    Standard,
+    /// Depth decreases with distance, from 1 at the near plane to 0 at the far plane, and the
+    /// depth buffer is cleared to 0. This gives much better precision over large distances, but
+    /// requires a clip-space depth range of 0 to 1 (e.g. `glClipControl(..., GL_ZERO_TO_ONE)`).
+    Reversed,
+}
+
+impl DepthConvention {
+    /// The depth test passing fragments at least as close as the terrain.
+    pub(crate) fn polyhedron_test(&self) -> gfx::state::Depth {
+        gfx::state::Depth {
+            fun: comparison(Pass::Polyhedron.state(*self).depth_test),
+            write: false,
         }
     }
 }
+
+/// How `DrapingRenderer` smooths the edges of draped polygons.
+///
+/// Which pixels a polygon covers is decided by a stencil test, so without antialiasing its edges
+/// are jagged, and shimmer as the camera moves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Antialiasing {
+    /// Run the stencil test for every sample of a multisampled target, rather than once per
+    /// pixel, so that edge pixels are partially covered once the target is resolved.
+    ///
+    /// The render target and depth-stencil target must be multisampled, with the same number of
+    /// samples, for this to have any effect.
+    pub multisample: bool,
+    /// The opacity, relative to the polygon's, that `DrapingRenderer::render_edge_fade` draws
+    /// fringes along polygons' edges with, or `None` to not draw them.
+    ///
+    /// This works without multisampled targets, and is cheap, but the fringe is a fixed width on
+    /// the terrain rather than on screen.
+    pub edge_fade: Option<f32>,
+}
+
+impl Antialiasing {
+    /// No antialiasing: edges are as sharp as the stencil test makes them.
+    pub fn none() -> Antialiasing {
+        Antialiasing {
+            multisample: false,
+            edge_fade: None,
+        }
+    }
+
+    fn samples(&self) -> Option<gfx::state::MultiSample> {
+        if self.multisample {
+            Some(gfx::state::MultiSample)
+        } else {
+            None
+        }
+    }
+}
+
+/// The size of each index in an index buffer.
+///
+/// 16-bit indices take half as much memory, and are the only kind supported by some older
+/// hardware, but can only address the first 65,536 vertices of a `PolygonBuffer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    U16,
+    U32,
+}
+
+/// The error returned when indices don't fit in the requested `IndexFormat`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexOverflowError {
+    index: u32,
+}
+
+impl IndexOverflowError {
+    /// The largest index that was asked to be stored.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl fmt::Display for IndexOverflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "index {} is too large for a 16-bit index buffer; use IndexFormat::U32 or split the \
+             PolygonBuffer into smaller buffers",
+            self.index
+        )
+    }
+}
+
+impl Error for IndexOverflowError {
+    fn description(&self) -> &str {
+        "index too large for a 16-bit index buffer"
+    }
+}
+
+/// The error returned when a `DrapingRenderer`'s shaders or pipelines, or the GPU resources
+/// polygons are drawn with, can't be created or updated.
+///
+/// Each shader error names the shader or program that failed, as `shader_programs` does, so that
+/// it can be found in the output of `gfx-draping check-shaders`.
+#[derive(Debug)]
+pub enum DrapingError {
+    /// A shader failed to compile. `name` ends in `.vert` or `.frag`.
+    Shader {
+        name: String,
+        error: gfx::shade::core::CreateShaderError,
+    },
+    /// A vertex and fragment shader failed to link into a program.
+    Link {
+        name: String,
+        error: gfx::shade::core::CreateProgramError,
+    },
+    /// A pipeline couldn't be created from a linked program, such as because the program's
+    /// inputs and outputs don't match the pipeline's.
+    Pipeline {
+        name: String,
+        error: gfx::PipelineStateError<String>,
+    },
+    /// A texture couldn't be created. `name` is the sampler it is bound to, or `mask` for a
+    /// `MaskTexture`.
+    Texture {
+        name: String,
+        error: gfx::CombinedError,
+    },
+    /// A vertex buffer couldn't be created. `name` is the geometry it holds, `polyhedra` or
+    /// `bounding_boxes`.
+    Buffer {
+        name: String,
+        error: gfx::buffer::CreationError,
+    },
+    /// A texture couldn't be updated. `name` is the sampler it is bound to.
+    Upload {
+        name: String,
+        error: gfx::UpdateError<[gfx::texture::Size; 3]>,
+    },
+}
+
+impl DrapingError {
+    /// The name of the shader, program, texture or buffer that failed.
+    pub fn name(&self) -> &str {
+        match *self {
+            DrapingError::Shader { ref name, .. } |
+            DrapingError::Link { ref name, .. } |
+            DrapingError::Pipeline { ref name, .. } |
+            DrapingError::Texture { ref name, .. } |
+            DrapingError::Buffer { ref name, .. } |
+            DrapingError::Upload { ref name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for DrapingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DrapingError::Shader { ref name, ref error } => {
+                write!(f, "could not compile shader {}: {}", name, error)
+            }
+            DrapingError::Link { ref name, ref error } => {
+                write!(f, "could not link shader program {}: {}", name, error)
+            }
+            DrapingError::Pipeline { ref name, ref error } => {
+                write!(f, "could not create pipeline for shader program {}: {}", name, error)
+            }
+            DrapingError::Texture { ref name, ref error } => {
+                write!(f, "could not create texture for sampler {}: {}", name, error)
+            }
+            DrapingError::Buffer { ref name, ref error } => {
+                write!(f, "could not create vertex buffer for {}: {}", name, error)
+            }
+            DrapingError::Upload { ref name, ref error } => {
+                write!(f, "could not update texture for sampler {}: {:?}", name, error)
+            }
+        }
+    }
+}
+
+impl Error for DrapingError {
+    fn description(&self) -> &str {
+        match *self {
+            DrapingError::Shader { .. } => "could not compile shader",
+            DrapingError::Link { .. } => "could not link shader program",
+            DrapingError::Pipeline { .. } => "could not create pipeline",
+            DrapingError::Texture { .. } => "could not create texture",
+            DrapingError::Buffer { .. } => "could not create vertex buffer",
+            DrapingError::Upload { .. } => "could not update texture",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn Error> {
+        match *self {
+            DrapingError::Shader { ref error, .. } => Some(error),
+            DrapingError::Link { ref error, .. } => Some(error),
+            DrapingError::Pipeline { ref error, .. } => Some(error),
+            DrapingError::Texture { ref error, .. } => Some(error),
+            DrapingError::Buffer { ref error, .. } => Some(error),
+            // Texture regions have no `Display` impl, so neither do their update errors.
+            DrapingError::Upload { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx_core::dummy::DummyResources;
+
+    use super::*;
+
+    /// Translates x by 100, far outside the view volume.
+    const AWAY: [[f32; 4]; 4] = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [100.0, 0.0, 0.0, 1.0],
+    ];
+
+    fn square(min: f32) -> Polygon {
+        let max = min + 1.0;
+        Polygon::new(
+            [(min, max), (0.0, 1.0)],
+            vec![(min, 0.0), (max, 0.0), (max, 1.0), (min, 1.0), (min, 0.0)],
+        )
+    }
+
+    // Index buffers aren't read without a device, so every index set shares an empty one.
+    fn renderable(indices: &PolygonBufferIndices) -> RenderablePolygonIndices<DummyResources> {
+        RenderablePolygonIndices {
+            polyhedron_buffer: gfx::IndexBuffer::Auto,
+            bounding_box_buffer: gfx::IndexBuffer::Auto,
+            polyhedron_ranges: iter::once(0..indices.polyhedron_indices.len() as u32).collect(),
+            bounding_box_ranges: iter::once(0..indices.bounding_box_indices.len() as u32).collect(),
+            polygons: indices.polygons.clone(),
+        }
+    }
+
+    const IDENTITY: [[f32; 4]; 4] = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+
+    fn command(pass: Pass, indices: Range<u32>) -> DrawCommand {
+        DrawCommand {
+            pass: pass,
+            indices: indices,
+        }
+    }
+
+    #[test]
+    fn rasterizers_cull_the_faces_each_pass_state_does() {
+        for &depth in &[DepthConvention::Standard, DepthConvention::Reversed] {
+            let polyhedron = rasterizer(Pass::Polyhedron, depth);
+            let cover = rasterizer(Pass::Cover, depth);
+
+            assert_eq!(Pass::Polyhedron.state(depth).cull_face, CullFace::Nothing);
+            assert_eq!(polyhedron.cull_face, gfx::state::CullFace::Nothing);
+            assert_eq!(Pass::Cover.state(depth).cull_face, CullFace::Front);
+            assert_eq!(cover.cull_face, gfx::state::CullFace::Front);
+        }
+    }
+
+    #[test]
+    fn culled_indices_draw_every_visible_polyhedron_before_any_bounding_box() {
+        let mut buffer = PolygonBuffer::new();
+        let mut indices = buffer.add(&square(-0.5));
+        indices.extend(&buffer.add(&square(2.0)));
+        indices.extend(&buffer.add(&square(-1.8)));
+        let (first, third) = (&indices.polygons[0], &indices.polygons[2]);
+
+        let mut recorder = DrawRecorder::new();
+        renderable(&indices).draw_commands(&mut recorder);
+        assert_eq!(
+            recorder.commands(),
+            &[
+                command(Pass::Polyhedron, 0..indices.polyhedron_indices.len() as u32),
+                command(Pass::Cover, 0..indices.bounding_box_indices.len() as u32),
+            ]
+        );
+
+        // The second square is outside the view, so the others can't be drawn as one range.
+        recorder.clear();
+        renderable(&indices).cull(IDENTITY).draw_commands(&mut recorder);
+        assert_eq!(
+            recorder.commands(),
+            &[
+                command(Pass::Polyhedron, first.polyhedron_indices.clone()),
+                command(Pass::Polyhedron, third.polyhedron_indices.clone()),
+                command(Pass::Cover, first.bounding_box_indices.clone()),
+                command(Pass::Cover, third.bounding_box_indices.clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn culling_everything_leaves_indices_that_can_be_culled_and_drawn() {
+        let mut buffer = PolygonBuffer::new();
+        let mut indices = buffer.add(&square(0.0));
+        indices.extend(&buffer.add(&square(2.0)));
+
+        let culled = renderable(&indices).cull(AWAY).cull(AWAY);
+        let mut recorder = DrawRecorder::new();
+        culled.draw_commands(&mut recorder);
+
+        assert!(recorder.commands().is_empty());
+    }
+
+    #[test]
+    fn neighbours_with_overlapping_bounding_boxes_are_drawn_one_at_a_time() {
+        // Two triangles splitting a square along its diagonal, so that their bounding boxes are
+        // the same square, though the triangles only share an edge.
+        let bounds = [(0.0, 1.0), (0.0, 1.0)];
+        let lower = Polygon::new(bounds, vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)]);
+        let upper = Polygon::new(bounds, vec![(1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (1.0, 0.0)]);
+        let cover = CoverVolume::BoundingBox;
+
+        let mut buffer = PolygonBuffer::new();
+        let mut indices = buffer.add(&lower.with_cover_volume(cover));
+        indices.extend(&buffer.add(&upper.with_cover_volume(cover)));
+        let (lower, upper) = (&indices.polygons[0], &indices.polygons[1]);
+        assert_eq!(lower.bounds, upper.bounds);
+
+        // Drawing both polyhedra first would leave the stencil set inside both triangles when
+        // the lower triangle's bounding box is drawn, and it would draw over the upper triangle.
+        let mut recorder = DrawRecorder::new();
+        renderable(&indices).draw_commands_per_polygon(&mut recorder);
+        assert_eq!(
+            recorder.commands(),
+            &[
+                command(Pass::Polyhedron, lower.polyhedron_indices.clone()),
+                command(Pass::Cover, lower.bounding_box_indices.clone()),
+                command(Pass::Polyhedron, upper.polyhedron_indices.clone()),
+                command(Pass::Cover, upper.bounding_box_indices.clone()),
+            ]
+        );
+
+        recorder.clear();
+        renderable(&indices).cull(IDENTITY).draw_commands_per_polygon(&mut recorder);
+        assert_eq!(recorder.commands().len(), 4);
+    }
+}
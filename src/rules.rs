@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use gfx;
+
+use polygon::*;
+use states::PolygonStates;
+
+/// A condition on a polygon's attributes.
+///
+/// Attributes are set with `PolygonBuffer::set_attribute`. A comparison against an attribute that
+/// a polygon doesn't have, or that has the wrong type, is false.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// The attribute with this name equals the value.
+    Equals(String, AttributeValue),
+    /// The attribute with this name is a number greater than the value.
+    GreaterThan(String, f64),
+    /// The attribute with this name is a number less than the value.
+    LessThan(String, f64),
+    /// The polygon has an attribute with this name.
+    Has(String),
+    /// The inner filter is false.
+    Not(Box<Filter>),
+    /// Every inner filter is true. An empty list is true.
+    All(Vec<Filter>),
+    /// At least one inner filter is true. An empty list is false.
+    Any(Vec<Filter>),
+}
+
+impl Filter {
+    /// Whether a polygon with `attributes` satisfies this filter.
+    pub fn matches(&self, attributes: &BTreeMap<String, AttributeValue>) -> bool {
+        let number = |name: &str| attributes.get(name).and_then(AttributeValue::as_number);
+
+        match *self {
+            Filter::Equals(ref name, ref value) => attributes.get(name) == Some(value),
+            Filter::GreaterThan(ref name, value) => number(name).filter(|&n| n > value).is_some(),
+            Filter::LessThan(ref name, value) => number(name).filter(|&n| n < value).is_some(),
+            Filter::Has(ref name) => attributes.contains_key(name),
+            Filter::Not(ref filter) => !filter.matches(attributes),
+            Filter::All(ref filters) => filters.iter().all(|filter| filter.matches(attributes)),
+            Filter::Any(ref filters) => filters.iter().any(|filter| filter.matches(attributes)),
+        }
+    }
+}
+
+/// A way of choosing the color of some polygons based on their attributes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorRule {
+    /// Polygons satisfying the filter are drawn in the given color.
+    Match(Filter, [f32; 4]),
+    /// Polygons are colored by the numeric attribute with the given name, linearly interpolating
+    /// between `(value, color)` stops. The stops must be sorted by value; values beyond the first
+    /// or last stop take that stop's color.
+    ///
+    /// Polygons without a numeric value for the attribute are left to the following rules.
+    Interpolate(String, Vec<(f64, [f32; 4])>),
+}
+
+impl ColorRule {
+    fn color(&self, attributes: &BTreeMap<String, AttributeValue>) -> Option<[f32; 4]> {
+        match *self {
+            ColorRule::Match(ref filter, color) => if filter.matches(attributes) {
+                Some(color)
+            } else {
+                None
+            },
+            ColorRule::Interpolate(ref name, ref stops) => {
+                let value = attributes.get(name).and_then(AttributeValue::as_number);
+                value.and_then(|value| Self::interpolate(stops, value))
+            }
+        }
+    }
+
+    fn interpolate(stops: &[(f64, [f32; 4])], value: f64) -> Option<[f32; 4]> {
+        let after = stops.iter().position(|&(stop, _)| stop > value);
+        match after {
+            _ if stops.is_empty() => None,
+            Some(0) => Some(stops[0].1),
+            None => Some(stops[stops.len() - 1].1),
+            Some(after) => {
+                let ((from, from_color), (to, to_color)) = (stops[after - 1], stops[after]);
+                let t = ((value - from) / (to - from)) as f32;
+
+                let mut color = from_color;
+                for (channel, to_channel) in color.iter_mut().zip(to_color.iter()) {
+                    *channel += (to_channel - *channel) * t;
+                }
+
+                Some(color)
+            }
+        }
+    }
+}
+
+/// An ordered list of `ColorRule`s, choosing each polygon's color from its attributes.
+///
+/// The first rule that gives a polygon a color wins. Polygons that no rule colors get the default
+/// color, or aren't drawn if there is no default.
+///
+/// Rules are evaluated on the CPU, by `evaluate`, which stores each polygon's color in a
+/// `PolygonStates`, so that all the polygons are drawn with a single call to
+/// `DrapingRenderer::render`. Or give the rules to a `Layer` with
+/// `Layer::set_color_rules`. Re-evaluating the rules, after changing them or adding polygons,
+/// only uploads a few bytes per polygon; the `PolygonBuffer`'s vertices are left alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColorRules {
+    rules: Vec<ColorRule>,
+    default: Option<[f32; 4]>,
+}
+
+impl ColorRules {
+    /// Create an empty list of rules, with no default color.
+    pub fn new() -> ColorRules {
+        ColorRules {
+            rules: Vec::new(),
+            default: None,
+        }
+    }
+
+    /// Add a rule after all the existing ones.
+    pub fn push(&mut self, rule: ColorRule) {
+        self.rules.push(rule);
+    }
+
+    /// Set the color of polygons that no rule colors, or `None` to not draw them.
+    pub fn set_default(&mut self, color: Option<[f32; 4]>) {
+        self.default = color;
+    }
+
+    /// The color a polygon with `attributes` is drawn in, if any.
+    pub fn color(&self, attributes: &BTreeMap<String, AttributeValue>) -> Option<[f32; 4]> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.color(attributes))
+            .next()
+            .or(self.default)
+    }
+
+    /// Color the polygons in `indices`, using the attributes stored in `buffer`, by setting their
+    /// colors in `states` with `PolygonStates::set_color`. Returns the polygons that got a color,
+    /// which are the ones to draw; polygons that no rule colors lose any color they had.
+    ///
+    /// `states` should have been created for `buffer`, and must be uploaded before drawing. Draw
+    /// the result in white, since polygons' own colors are multiplied by the renderer's color.
+    /// Polygons drawn with states are drawn one at a time, so each keeps its own color even where
+    /// its bounding box overlaps another polygon's.
+    pub fn evaluate<R: gfx::Resources>(
+        &self,
+        buffer: &PolygonBuffer,
+        indices: &PolygonBufferIndices,
+        states: &mut PolygonStates<R>,
+    ) -> PolygonBufferIndices {
+        let no_attributes = BTreeMap::new();
+        let mut colored = PolygonBufferIndices::new();
+
+        for polygon in &indices.polygons {
+            let attributes = buffer.attributes(polygon.id).unwrap_or(&no_attributes);
+            let color = self.color(attributes);
+            states.set_color(polygon.id, color);
+
+            if color.is_some() {
+                colored.push(indices, polygon);
+            }
+        }
+
+        colored
+    }
+}
@@ -0,0 +1,350 @@
+use gfx;
+
+use fill::FillShader;
+use polygon::{PolygonBuffer, PolygonBufferIndices};
+use render::*;
+use rules::ColorRules;
+use states::PolygonStates;
+
+/// A collection of styled layers of draped polygons, rendered together.
+///
+/// Layers are drawn in ascending order of `Layer::order`; layers with the same order are drawn in
+/// the order they were added. Each layer's polygons are culled against the view frustum before
+/// drawing.
+#[derive(Clone, Debug)]
+pub struct DrapingScene<R: gfx::Resources> {
+    renderer: DrapingRenderer<R>,
+    layers: Vec<(LayerId, Layer<R>)>,
+    next_id: u32,
+}
+
+impl<R: gfx::Resources> DrapingScene<R> {
+    /// Construct an empty scene, setting up a `DrapingRenderer` for terrain drawn with the `depth`
+    /// convention.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, depth: DepthConvention) -> DrapingScene<R> {
+        DrapingScene::with_renderer(DrapingRenderer::new(factory, depth))
+    }
+
+    /// Construct an empty scene drawn with `renderer`, such as one created with
+    /// `DrapingRenderer::with_antialiasing`.
+    pub fn with_renderer(renderer: DrapingRenderer<R>) -> DrapingScene<R> {
+        DrapingScene {
+            renderer: renderer,
+            layers: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Add a layer to the scene, returning an ID with which to refer to it later.
+    pub fn add_layer(&mut self, layer: Layer<R>) -> LayerId {
+        let id = LayerId(self.next_id);
+        self.next_id += 1;
+        self.layers.push((id, layer));
+        id
+    }
+
+    /// Remove a layer from the scene, returning it if it was present.
+    pub fn remove_layer(&mut self, id: LayerId) -> Option<Layer<R>> {
+        let position = self.layers.iter().position(|&(layer_id, _)| layer_id == id);
+        position.map(|position| self.layers.remove(position).1)
+    }
+
+    /// Get a layer in the scene.
+    pub fn layer(&self, id: LayerId) -> Option<&Layer<R>> {
+        self.layers
+            .iter()
+            .find(|&&(layer_id, _)| layer_id == id)
+            .map(|(_, layer)| layer)
+    }
+
+    /// Get a layer in the scene, in order to change its style, visibility or order.
+    pub fn layer_mut(&mut self, id: LayerId) -> Option<&mut Layer<R>> {
+        self.layers
+            .iter_mut()
+            .find(|&&mut (layer_id, _)| layer_id == id)
+            .map(|&mut (_, ref mut layer)| layer)
+    }
+
+    /// The IDs of the layers in the scene, in the order they are drawn.
+    pub fn layer_ids(&self) -> Vec<LayerId> {
+        self.draw_order().into_iter().map(|&(id, _)| id).collect()
+    }
+
+    /// Render every visible layer.
+    ///
+    /// Only polygons whose validity interval contains `time` are drawn. Changes to the layers'
+    /// `PolygonStates` are uploaded first, and `time` also drives their animations. The
+    /// requirements on `depth_stencil_target` and `mvp` are the same as for
+    /// `DrapingRenderer::render`.
+    pub fn render<C: gfx::CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        render_target: gfx::handle::RenderTargetView<R, gfx::format::Srgba8>,
+        depth_stencil_target: gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        mvp: [[f32; 4]; 4],
+        time: f32,
+    ) {
+        for &mut (_, ref mut layer) in &mut self.layers {
+            if let Some(ref mut states) = layer.states {
+                states.upload(encoder);
+            }
+        }
+
+        for (_, layer) in self.draw_order() {
+            if !layer.visible {
+                continue;
+            }
+
+            // The fill shader colors the fill and its fringes, but not the outline. Polygons
+            // colored by rules have their colors in the layer's states, and are drawn in white
+            // so that those colors are left as they are. Fringe and outline polygons have IDs of
+            // their own, so the states, which are indexed by the fill's IDs, aren't bound for
+            // them.
+            let fill = layer.fill_shader.as_ref();
+            let states = layer.states.as_ref();
+            let mut passes: Vec<_> = match layer.colored {
+                Some(ref colored) => vec![([1.0; 4], colored, fill, states)],
+                None => vec![(layer.style.fill_color, &layer.fill, fill, states)],
+            };
+            if let Some(ref edge_fade) = layer.edge_fade {
+                if layer.colored.is_none() {
+                    let color = self.renderer.edge_fade_color(layer.style.fill_color);
+                    passes.extend(color.map(|color| (color, edge_fade, fill, None)));
+                }
+            }
+            if let Some(ref outline) = layer.outline {
+                let color = layer.style.outline_color;
+                passes.extend(color.map(|color| (color, outline, None, None)));
+            }
+
+            for (color, indices, fill, states) in passes {
+                let color = [
+                    color[0],
+                    color[1],
+                    color[2],
+                    color[3] * layer.style.opacity,
+                ];
+
+                let indices = indices.cull(mvp);
+                let mut params = RenderParams::new(mvp, color, &layer.buffer, &indices);
+                params.states = states;
+                params.time = Some(time);
+
+                self.renderer.draw(
+                    encoder,
+                    render_target.clone(),
+                    depth_stencil_target.clone(),
+                    layer.style.blend,
+                    fill,
+                    params,
+                );
+            }
+        }
+    }
+
+    fn draw_order(&self) -> Vec<&(LayerId, Layer<R>)> {
+        // `sort_by_key` is stable, so ties keep the order the layers were added in.
+        let mut layers: Vec<_> = self.layers.iter().collect();
+        layers.sort_by_key(|(_, layer)| layer.order);
+        layers
+    }
+}
+
+/// Identifies a layer within a `DrapingScene`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LayerId(u32);
+
+/// A set of polygons drawn with a common style.
+///
+/// A layer owns handles to its buffer and indices. These are reference-counted, so several layers
+/// can cheaply share one `RenderablePolygonBuffer` by cloning it.
+#[derive(Clone, Debug)]
+pub struct Layer<R: gfx::Resources> {
+    buffer: RenderablePolygonBuffer<R>,
+    fill: RenderablePolygonIndices<R>,
+    outline: Option<RenderablePolygonIndices<R>>,
+    edge_fade: Option<RenderablePolygonIndices<R>>,
+    // The polygons of the fill that color rules gave a color, if the layer has color rules.
+    colored: Option<RenderablePolygonIndices<R>>,
+    states: Option<PolygonStates<R>>,
+    fill_shader: Option<FillShader<R>>,
+    style: Style,
+    visible: bool,
+    order: i32,
+}
+
+impl<R: gfx::Resources> Layer<R> {
+    /// Construct a visible layer, with order 0, filling the polygons in `fill`.
+    pub fn new(
+        buffer: RenderablePolygonBuffer<R>,
+        fill: RenderablePolygonIndices<R>,
+        style: Style,
+    ) -> Layer<R> {
+        Layer {
+            buffer: buffer,
+            fill: fill,
+            outline: None,
+            edge_fade: None,
+            colored: None,
+            states: None,
+            fill_shader: None,
+            style: style,
+            visible: true,
+            order: 0,
+        }
+    }
+
+    /// Give this layer outlines, drawn on top of its fill.
+    ///
+    /// `outline` should index into the same buffer as the fill, and usually contains the
+    /// `Polygon::outline` of each polygon in the fill. Outlines are only drawn while the layer's
+    /// style has an `outline_color`.
+    ///
+    /// Outlines are drawn without the layer's `PolygonStates`, since their polygons have IDs of
+    /// their own: they stay visible, unhighlighted and unfaded whatever the state of the polygons
+    /// they outline.
+    pub fn with_outline(mut self, outline: RenderablePolygonIndices<R>) -> Layer<R> {
+        self.outline = Some(outline);
+        self
+    }
+
+    /// Soften the edges of this layer's fill with fringes, drawn between the fill and outline.
+    ///
+    /// `edge_fade` should index into the same buffer as the fill, and usually contains the
+    /// `Polygon::edge_fade` of each polygon in the fill. Fringes are only drawn if the scene's
+    /// renderer has an `Antialiasing::edge_fade`, and not while the layer has color rules, since
+    /// they are drawn in the style's `fill_color`.
+    ///
+    /// Like outlines, fringes are drawn without the layer's `PolygonStates`, since their polygons
+    /// have IDs of their own.
+    pub fn with_edge_fade(mut self, edge_fade: RenderablePolygonIndices<R>) -> Layer<R> {
+        self.edge_fade = Some(edge_fade);
+        self
+    }
+
+    /// Hide, highlight and fade this layer's polygons individually, according to `states`.
+    ///
+    /// `states` should have been created for this layer's buffer. Change it with `states_mut`;
+    /// the changes are uploaded by `DrapingScene::render`. The states apply to the fill, but not
+    /// to its fringes or outlines.
+    pub fn with_states(mut self, states: PolygonStates<R>) -> Layer<R> {
+        self.states = Some(states);
+        self
+    }
+
+    /// Fill this layer's polygons, and their fringes, using `fill`, which must have been created
+    /// for the scene's renderer. Outlines are still drawn in the style's `outline_color`.
+    pub fn with_fill_shader(mut self, fill: FillShader<R>) -> Layer<R> {
+        self.fill_shader = Some(fill);
+        self
+    }
+
+    /// The fill shader of this layer, if it has one.
+    pub fn fill_shader(&self) -> Option<&FillShader<R>> {
+        self.fill_shader.as_ref()
+    }
+
+    /// Change the fill shader of this layer, such as to set its parameters, if it has one.
+    pub fn fill_shader_mut(&mut self) -> Option<&mut FillShader<R>> {
+        self.fill_shader.as_mut()
+    }
+
+    /// The state of this layer's polygons, if it has any.
+    pub fn states(&self) -> Option<&PolygonStates<R>> {
+        self.states.as_ref()
+    }
+
+    /// Change the state of this layer's polygons, if it has any.
+    pub fn states_mut(&mut self) -> Option<&mut PolygonStates<R>> {
+        self.states.as_mut()
+    }
+
+    /// Fill this layer's polygons with colors chosen by `rules`, rather than the style's
+    /// `fill_color`.
+    ///
+    /// `buffer` should be the buffer this layer draws from, and holds the polygons' attributes.
+    /// `indices` should contain the same polygons as the fill this layer was created with. Call
+    /// this again whenever the rules or attributes change.
+    ///
+    /// The colors are stored in the layer's `PolygonStates`, which are created for `buffer` if
+    /// the layer has none. Like any polygons drawn with states, the fill is then drawn one polygon
+    /// at a time, so that neighbours whose bounding boxes overlap don't take each other's colors.
+    pub fn set_color_rules<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        buffer: &PolygonBuffer,
+        indices: &PolygonBufferIndices,
+        rules: &ColorRules,
+    ) {
+        if self.states.is_none() {
+            self.states = Some(PolygonStates::new(factory, buffer));
+        }
+
+        let states = self.states.as_mut().unwrap();
+        self.colored = Some(rules.evaluate(buffer, indices, states).as_renderable(factory));
+    }
+
+    /// Go back to filling this layer's polygons with the style's `fill_color`.
+    pub fn clear_color_rules(&mut self) {
+        if let Some(ref mut states) = self.states {
+            states.clear_colors();
+        }
+
+        self.colored = None;
+    }
+
+    /// How this layer is drawn.
+    pub fn style(&self) -> &Style {
+        &self.style
+    }
+
+    /// Change how this layer is drawn.
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    /// Whether this layer is drawn at all.
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Show or hide this layer.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    /// Where this layer is drawn relative to others. Layers with a higher order are drawn on top.
+    pub fn order(&self) -> i32 {
+        self.order
+    }
+
+    /// Move this layer above or below others.
+    pub fn set_order(&mut self, order: i32) {
+        self.order = order;
+    }
+}
+
+/// How the polygons of a `Layer` are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Style {
+    /// The color the polygons are filled with, unless the layer has color rules.
+    pub fill_color: [f32; 4],
+    /// How the fill and outline are combined with the terrain.
+    pub blend: BlendMode,
+    /// A factor applied to the alpha of both the fill and the outline.
+    pub opacity: f32,
+    /// The color of the layer's outlines, or `None` to not draw them.
+    pub outline_color: Option<[f32; 4]>,
+}
+
+impl Style {
+    /// A style filling polygons with `fill_color`, alpha-blended, with no outline.
+    pub fn new(fill_color: [f32; 4]) -> Style {
+        Style {
+            fill_color: fill_color,
+            blend: BlendMode::Alpha,
+            opacity: 1.0,
+            outline_color: None,
+        }
+    }
+}
@@ -1,9 +1,170 @@
 #version 150 core
 
+// When drawing into a deferred renderer's G-buffer, `TARGET_COUNT` is defined as the number of
+// targets, `o_target0` onwards. When drawing into a mask, `MASK` is defined and the target is
+// `o_mask`. Otherwise, there is a single target, `o_color`.
+//
+// When `CUSTOM_FILL` is defined, the source of a `FillShader` follows this one, and defines
+// `fill_color`, which decides the color of each fragment before states are applied.
+
 uniform vec4 u_color;
+uniform vec4 u_highlight_color;
+uniform float u_highlight_pulse;
+uniform sampler2D t_states;
+uniform sampler2D t_colors;
+uniform sampler2D t_fades;
+uniform int u_use_states;
+uniform float u_time;
+
+flat in uint v_polygon_id;
+
+#ifdef CUSTOM_FILL
+uniform mat4 u_mvp;
+uniform mat4 u_fill_params;
+uniform sampler2D t_fill;
+uniform sampler2D t_fill_attributes;
+
+// Texel 0 of `t_fill_attributes` is all NaN, and each polygon's attributes follow it, so that
+// polygons beyond the texture get NaN too.
+vec4 fill_attributes() {
+    ivec2 size = textureSize(t_fill_attributes, 0);
+    int index = int(v_polygon_id) + 1;
+    ivec2 texel = ivec2(index % size.x, index / size.x);
+    if (texel.y >= size.y) {
+        texel = ivec2(0);
+    }
+
+    return texelFetch(t_fill_attributes, texel, 0);
+}
+
+vec4 fill_color(vec4 color);
+#endif
+
+#ifdef TARGET_COUNT
+// Bit `i` of each mask applies to `o_target{i}`. Column `i` of `u_target_constants` is the
+// constant written to `o_target{i}`, if its bit in `u_constant_mask` is set.
+uniform mat4 u_target_constants;
+uniform int u_constant_mask;
+uniform int u_multiply_mask;
+
+// GLSL ES needs an explicit location for each of several outputs, where desktop GLSL assigns
+// them from the pipeline's bindings.
+#ifdef GL_ES
+#define LOCATION(n) layout(location = n)
+#else
+#define LOCATION(n)
+#endif
+
+LOCATION(0) out vec4 o_target0;
+#if TARGET_COUNT > 1
+LOCATION(1) out vec4 o_target1;
+#endif
+#if TARGET_COUNT > 2
+LOCATION(2) out vec4 o_target2;
+#endif
+#if TARGET_COUNT > 3
+LOCATION(3) out vec4 o_target3;
+#endif
+#elif defined(MASK)
+// The index of a `MaskOutput`: coverage, polygon ID or color.
+uniform int u_mask_output;
+
+out vec4 o_mask;
+#else
+uniform int u_multiply;
 
 out vec4 o_color;
+#endif
+
+// Multiplying by white leaves the terrain unchanged, so fade towards it.
+vec4 multiply_fade(vec4 color) {
+    return vec4(mix(vec3(1.0), color.rgb, color.a), 1.0);
+}
+
+#ifdef TARGET_COUNT
+vec4 target_output(int target, vec4 color) {
+    if ((u_constant_mask & (1 << target)) != 0) {
+        color = vec4(u_target_constants[target].rgb, u_target_constants[target].a * color.a);
+    }
+
+    if ((u_multiply_mask & (1 << target)) != 0) {
+        color = multiply_fade(color);
+    }
+
+    return color;
+}
+#endif
 
 void main() {
-    o_color = u_color;
+    vec4 color = u_color;
+    vec4 state = vec4(1.0, 0.0, 1.0, 0.0);
+    vec4 fade = vec4(0.0, 0.0, 1.0, 0.0);
+    if (u_use_states != 0) {
+        int width = textureSize(t_states, 0).x;
+        int id = int(v_polygon_id);
+        ivec2 texel = ivec2(id % width, id / width);
+        state = texelFetch(t_states, texel, 0);
+
+        // The fade texel holds the start time, duration and starting opacity of the latest fade
+        // towards the opacity in the state texel, and the time the highlight started.
+        fade = texelFetch(t_fades, texel, 0);
+
+        // A hidden polygon's bounding box may overlap visible polygons, so it must not draw over
+        // them, nor reset the stencil buffer before their own bounding boxes are drawn.
+        if (state.r < 0.5) {
+            discard;
+        }
+
+        // The polygon's own color, such as one chosen by `ColorRules`.
+        if (state.a >= 0.5) {
+            color *= texelFetch(t_colors, texel, 0);
+        }
+    }
+
+#ifdef CUSTOM_FILL
+    color = fill_color(color);
+#endif
+
+    if (u_use_states != 0) {
+        if (state.g >= 0.5) {
+            float amount = 1.0;
+            if (u_highlight_pulse > 0.0) {
+                float elapsed = max(u_time - fade.w, 0.0);
+                amount = 0.5 - 0.5 * cos(6.2831853 * elapsed / u_highlight_pulse);
+            }
+
+            color = mix(color, u_highlight_color, amount);
+        }
+
+        float progress = 1.0;
+        if (fade.y > 0.0) {
+            progress = clamp((u_time - fade.x) / fade.y, 0.0, 1.0);
+        }
+
+        color.a *= mix(fade.z, state.b, progress);
+    }
+
+#ifdef TARGET_COUNT
+    o_target0 = target_output(0, color);
+#if TARGET_COUNT > 1
+    o_target1 = target_output(1, color);
+#endif
+#if TARGET_COUNT > 2
+    o_target2 = target_output(2, color);
+#endif
+#if TARGET_COUNT > 3
+    o_target3 = target_output(3, color);
+#endif
+#elif defined(MASK)
+    if (u_mask_output == 0) {
+        o_mask = vec4(color.a);
+    } else if (u_mask_output == 1) {
+        uint id = v_polygon_id + 1u;
+        o_mask = vec4(uvec4(id, id >> 8, id >> 16, id >> 24) & 255u) / 255.0;
+    } else {
+        o_mask = color;
+    }
+#else
+    o_color = u_multiply != 0 ? multiply_fade(color) : color;
+#endif
 }
@@ -1,9 +1,41 @@
 #version 150 core
 
 uniform mat4 u_mvp;
+uniform int u_filter_by_time;
+uniform float u_time;
+// One texel per polygon, holding the start and end of its validity interval.
+uniform sampler2D t_validity;
 
 in vec3 a_position;
+in uint a_polygon_id;
+
+flat out uint v_polygon_id;
 
 void main() {
+    v_polygon_id = a_polygon_id;
+
+    if (u_filter_by_time != 0) {
+        int width = textureSize(t_validity, 0).x;
+        int id = int(a_polygon_id);
+        vec2 validity = texelFetch(t_validity, ivec2(id % width, id / width), 0).xy;
+
+        if (u_time < validity.x || u_time >= validity.y) {
+            // Every vertex of the polygon is moved to the same point outside the view volume, so
+            // all of its triangles are degenerate and get clipped.
+            gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+            return;
+        }
+    }
+
     gl_Position = u_mvp * vec4(a_position, 1.0);
+
+    // The far side of the shadow volume must not be clipped by the far plane, so vertices beyond
+    // it are pulled onto it, much like depth clamping.
+    if (gl_Position.w > 0.0) {
+#ifdef REVERSED_Z
+        gl_Position.z = max(gl_Position.z, 0.0);
+#else
+        gl_Position.z = min(gl_Position.z, gl_Position.w);
+#endif
+    }
 }
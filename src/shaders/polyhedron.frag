@@ -1,7 +1,23 @@
 #version 150 core
 
+uniform sampler2D t_states;
+uniform int u_use_states;
+
+flat in uint v_polygon_id;
+
 out vec4 o_color;
 
 void main() {
+    if (u_use_states != 0) {
+        int width = textureSize(t_states, 0).x;
+        int id = int(v_polygon_id);
+        vec4 state = texelFetch(t_states, ivec2(id % width, id / width), 0);
+
+        // A hidden polygon must not touch the stencil buffer at all.
+        if (state.r < 0.5) {
+            discard;
+        }
+    }
+
     o_color = vec4(0.0, 0.0, 0.0, 0.0);
 }
@@ -0,0 +1,411 @@
+use std::ops::Range;
+
+use gfx;
+use gfx::format::{R32_G32_B32_A32, R8_G8_B8_A8, Rgba32F, Rgba8};
+
+use polygon::{PolygonBuffer, PolygonId};
+use render::DrapingError;
+
+/// The widest the state texture is allowed to get, in texels. Larger tables wrap onto more rows.
+const MAX_WIDTH: usize = 1024;
+
+/// Per-polygon visibility, highlighting, opacity and color, stored on the GPU.
+///
+/// Changing a polygon's state only marks it as changed; call `upload` before rendering to send
+/// the changes to the GPU. Only the texels that changed are uploaded, so toggling a few polygons
+/// every frame is cheap, unlike rebuilding a `PolygonBufferIndices` without them.
+///
+/// Pass the table to `DrapingRenderer::render` with `RenderParams::with_states`, or give it to a
+/// `Layer` with `Layer::with_states`. When drawn with a table:
+///
+/// * Hidden polygons aren't drawn at all.
+/// * Polygons with a color of their own (see `set_color`, and `ColorRules::evaluate`) are drawn
+///   in it, multiplied by the color passed to the renderer.
+/// * Highlighted polygons are drawn in the table's highlight color rather than their usual color.
+/// * Each polygon's alpha is multiplied by its opacity.
+///
+/// Opacity changes can be animated with `fade_opacity`, and highlighted polygons can pulse (see
+/// `set_highlight_pulse`). Animations are evaluated on the GPU, using the time passed to
+/// `RenderParams::with_time`, so they need no uploads while they run. Times are in
+/// whatever unit and from whatever epoch you like, typically seconds since your application
+/// started; keep them small, as they are stored as `f32`.
+///
+/// Polygons drawn with a table are drawn one at a time, each polyhedron followed by its bounding
+/// box, so that every pixel is drawn in the state of the polygon it lies in, even where the
+/// bounding boxes of neighbouring polygons overlap. This takes two draw calls per polygon rather
+/// than two per set of indices. Where polygons themselves overlap, each is drawn, in the order of
+/// the indices.
+#[derive(Clone, Debug)]
+pub struct PolygonStates<R: gfx::Resources> {
+    // One texel per polygon: visibility, highlighting and opacity are stored in the red, green
+    // and blue channels, and whether the polygon has a color of its own in the alpha channel.
+    // Padded with visible polygons to fill the texture.
+    states: Vec<[u8; 4]>,
+    // One texel per polygon, laid out like `states`: the polygon's own color, if it has one.
+    colors: Vec<[u8; 4]>,
+    // One texel per polygon, laid out like `states`: the start time, duration and starting
+    // opacity of the polygon's latest fade, and the time its highlight started.
+    fades: Vec<[f32; 4]>,
+    num_polygons: usize,
+    width: usize,
+    texture: gfx::handle::Texture<R, R8_G8_B8_A8>,
+    pub(crate) view: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    color_texture: gfx::handle::Texture<R, R8_G8_B8_A8>,
+    pub(crate) color_view: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    fade_texture: gfx::handle::Texture<R, R32_G32_B32_A32>,
+    pub(crate) fade_view: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    dirty: Option<Range<usize>>,
+    highlight_color: [f32; 4],
+    highlight_pulse: Option<f32>,
+}
+
+impl<R: gfx::Resources> PolygonStates<R> {
+    /// Create a table for every polygon currently in `buffer`, all visible, not highlighted, fully
+    /// opaque and without colors of their own.
+    ///
+    /// Polygons added to `buffer` later are not in the table, and must not be rendered with it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer has too many polygons to fit in a texture, i.e. more than 64 million,
+    /// or if the textures can't be created; see `try_new`.
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F, buffer: &PolygonBuffer) -> PolygonStates<R> {
+        Self::try_new(factory, buffer).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `new`, but returns an error rather than panicking if the textures can't be created.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer has too many polygons to fit in a texture, i.e. more than 64 million.
+    pub fn try_new<F: gfx::Factory<R>>(
+        factory: &mut F,
+        buffer: &PolygonBuffer,
+    ) -> Result<PolygonStates<R>, DrapingError> {
+        let num_polygons = buffer.num_polygons as usize;
+        let (width, height) = texture_size(num_polygons);
+
+        let kind =
+            gfx::texture::Kind::D2(width as u16, height as u16, gfx::texture::AaMode::Single);
+        let (texture, view) = Self::texture::<Rgba8, F>(factory, "t_states", kind)?;
+        let (color_texture, color_view) = Self::texture::<Rgba8, F>(factory, "t_colors", kind)?;
+        let (fade_texture, fade_view) = Self::texture::<Rgba32F, F>(factory, "t_fades", kind)?;
+
+        let len = width * height;
+        Ok(PolygonStates {
+            states: vec![[255, 0, 255, 0]; len],
+            colors: vec![[255; 4]; len],
+            fades: vec![[0.0, 0.0, 1.0, 0.0]; len],
+            num_polygons: num_polygons,
+            width: width,
+            texture: texture,
+            view: view,
+            color_texture: color_texture,
+            color_view: color_view,
+            fade_texture: fade_texture,
+            fade_view: fade_view,
+            dirty: Some(0..len),
+            highlight_color: [1.0, 1.0, 0.0, 1.0],
+            highlight_pulse: None,
+        })
+    }
+
+    /// The number of polygons in the table.
+    pub fn len(&self) -> usize {
+        self.num_polygons
+    }
+
+    /// Whether the table has no polygons.
+    pub fn is_empty(&self) -> bool {
+        self.num_polygons == 0
+    }
+
+    /// Whether a polygon is drawn.
+    pub fn is_visible(&self, id: PolygonId) -> bool {
+        self.state(id)[0] != 0
+    }
+
+    /// Show or hide a polygon.
+    pub fn set_visible(&mut self, id: PolygonId, visible: bool) {
+        self.set_channel(id, 0, if visible { 255 } else { 0 });
+    }
+
+    /// Whether a polygon is drawn in the highlight color.
+    pub fn is_highlighted(&self, id: PolygonId) -> bool {
+        self.state(id)[1] != 0
+    }
+
+    /// Highlight a polygon, or stop highlighting it. A pulsing highlight starts at time 0; use
+    /// `set_highlighted_at` to start it when the polygon is highlighted instead.
+    pub fn set_highlighted(&mut self, id: PolygonId, highlighted: bool) {
+        self.set_highlighted_at(id, highlighted, 0.0);
+    }
+
+    /// Highlight a polygon, or stop highlighting it, at `time`. If highlighted polygons pulse,
+    /// this polygon's pulse starts from its usual color at `time`. Highlighting a polygon that is
+    /// already highlighted doesn't restart its pulse.
+    pub fn set_highlighted_at(&mut self, id: PolygonId, highlighted: bool, time: f32) {
+        if highlighted && !self.is_highlighted(id) {
+            let index = id.0 as usize;
+            self.fades[index][3] = time;
+            self.mark_dirty(index);
+        }
+
+        self.set_channel(id, 1, if highlighted { 255 } else { 0 });
+    }
+
+    /// The factor a polygon's alpha is multiplied by, once any fade has finished.
+    pub fn opacity(&self, id: PolygonId) -> f32 {
+        self.state(id)[2] as f32 / 255.0
+    }
+
+    /// The factor a polygon's alpha is multiplied by at `time`, taking fades into account.
+    pub fn opacity_at(&self, id: PolygonId, time: f32) -> f32 {
+        assert!((id.0 as usize) < self.num_polygons, "{:?} is not in the table", id);
+        let [start, duration, from, _] = self.fades[id.0 as usize];
+        let progress = if duration > 0.0 {
+            ((time - start) / duration).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        from + (self.opacity(id) - from) * progress
+    }
+
+    /// Change the factor a polygon's alpha is multiplied by, immediately. It is clamped between 0
+    /// and 1, and stored with 8 bits of precision.
+    pub fn set_opacity(&mut self, id: PolygonId, opacity: f32) {
+        self.fade_opacity(id, opacity, 0.0, 0.0);
+    }
+
+    /// Smoothly change the factor a polygon's alpha is multiplied by, starting at `start_time`
+    /// and taking `duration` to reach `opacity`.
+    ///
+    /// The fade starts from the polygon's opacity at `start_time`, so interrupting one fade with
+    /// another doesn't make the polygon jump. To fade a hidden polygon in, set its opacity to 0,
+    /// make it visible, and then fade it to 1.
+    pub fn fade_opacity(&mut self, id: PolygonId, opacity: f32, start_time: f32, duration: f32) {
+        let from = self.opacity_at(id, start_time);
+        let opacity = (opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+        self.set_channel(id, 2, opacity);
+
+        let index = id.0 as usize;
+        let highlight_start = self.fades[index][3];
+        self.fades[index] = [start_time, duration, from, highlight_start];
+        self.mark_dirty(index);
+    }
+
+    /// The color a polygon is drawn in instead of the color passed to the renderer, if it has one.
+    pub fn color(&self, id: PolygonId) -> Option<[f32; 4]> {
+        if self.state(id)[3] == 0 {
+            return None;
+        }
+
+        let color = self.colors[id.0 as usize];
+        Some([
+            color[0] as f32 / 255.0,
+            color[1] as f32 / 255.0,
+            color[2] as f32 / 255.0,
+            color[3] as f32 / 255.0,
+        ])
+    }
+
+    /// Give a polygon a color of its own, or go back to the color passed to the renderer with
+    /// `None`. The color is multiplied by the one passed to the renderer, so pass white to draw
+    /// polygons in exactly their own colors, and is stored with 8 bits per channel.
+    pub fn set_color(&mut self, id: PolygonId, color: Option<[f32; 4]>) {
+        self.set_channel(id, 3, if color.is_some() { 255 } else { 0 });
+
+        if let Some(color) = color {
+            let quantize = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+            let color = [
+                quantize(color[0]),
+                quantize(color[1]),
+                quantize(color[2]),
+                quantize(color[3]),
+            ];
+
+            let index = id.0 as usize;
+            if self.colors[index] != color {
+                self.colors[index] = color;
+                self.mark_dirty(index);
+            }
+        }
+    }
+
+    /// Take away every polygon's own color, as with `set_color(id, None)`.
+    pub fn clear_colors(&mut self) {
+        for id in 0..self.num_polygons {
+            self.set_color(PolygonId(id as u32), None);
+        }
+    }
+
+    /// The color highlighted polygons are drawn in. Yellow by default.
+    pub fn highlight_color(&self) -> [f32; 4] {
+        self.highlight_color
+    }
+
+    /// Change the color highlighted polygons are drawn in.
+    pub fn set_highlight_color(&mut self, color: [f32; 4]) {
+        self.highlight_color = color;
+    }
+
+    /// How long one pulse of a highlighted polygon takes, if they pulse.
+    pub fn highlight_pulse(&self) -> Option<f32> {
+        self.highlight_pulse
+    }
+
+    /// Make highlighted polygons pulse between their usual color and the highlight color, once
+    /// every `period`, or stop them pulsing with `None`. Each polygon's pulse starts from its
+    /// usual color when it is highlighted; see `set_highlighted_at`.
+    pub fn set_highlight_pulse(&mut self, period: Option<f32>) {
+        self.highlight_pulse = period;
+    }
+
+    /// Send any changes made since the last upload to the GPU.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the textures can't be updated; see `try_upload`.
+    pub fn upload<C: gfx::CommandBuffer<R>>(&mut self, encoder: &mut gfx::Encoder<R, C>) {
+        self.try_upload(encoder).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `upload`, but returns an error rather than panicking if the textures can't be
+    /// updated. The changes are then kept, to be sent by the next upload.
+    pub fn try_upload<C: gfx::CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) -> Result<(), DrapingError> {
+        let dirty = match self.dirty.clone() {
+            Some(dirty) => dirty,
+            None => return Ok(()),
+        };
+
+        let (first_row, last_row) = (dirty.start / self.width, (dirty.end - 1) / self.width);
+
+        // Within a single row, only the changed texels are uploaded. Otherwise, whole rows are.
+        let (range, x, width) = if first_row == last_row {
+            (dirty.clone(), dirty.start % self.width, dirty.end - dirty.start)
+        } else {
+            (
+                first_row * self.width..(last_row + 1) * self.width,
+                0,
+                self.width,
+            )
+        };
+
+        let fades: Vec<_> = self.fades[range.clone()]
+            .iter()
+            .map(|fade| {
+                [
+                    fade[0].to_bits(),
+                    fade[1].to_bits(),
+                    fade[2].to_bits(),
+                    fade[3].to_bits(),
+                ]
+            })
+            .collect();
+
+        let info = gfx::texture::NewImageInfo {
+            xoffset: x as u16,
+            yoffset: first_row as u16,
+            zoffset: 0,
+            width: width as u16,
+            height: (last_row - first_row + 1) as u16,
+            depth: 0,
+            format: (),
+            mipmap: 0,
+        };
+
+        let upload_error = |name: &str| {
+            let name = name.to_owned();
+            move |error| DrapingError::Upload {
+                name: name,
+                error: error,
+            }
+        };
+
+        let (states, colors) = (&self.states[range.clone()], &self.colors[range]);
+        encoder
+            .update_texture::<R8_G8_B8_A8, Rgba8>(&self.texture, None, info, states)
+            .map_err(upload_error("t_states"))?;
+        encoder
+            .update_texture::<R8_G8_B8_A8, Rgba8>(&self.color_texture, None, info, colors)
+            .map_err(upload_error("t_colors"))?;
+        encoder
+            .update_texture::<R32_G32_B32_A32, Rgba32F>(&self.fade_texture, None, info, &fades)
+            .map_err(upload_error("t_fades"))?;
+
+        self.dirty = None;
+        Ok(())
+    }
+
+    fn texture<T: gfx::format::TextureFormat, F: gfx::Factory<R>>(
+        factory: &mut F,
+        name: &str,
+        kind: gfx::texture::Kind,
+    ) -> Result<StateTexture<R, T>, DrapingError> {
+        let error = |error: gfx::CombinedError| DrapingError::Texture {
+            name: name.to_owned(),
+            error: error,
+        };
+
+        let texture = factory
+            .create_texture(
+                kind,
+                1,
+                gfx::memory::Bind::SHADER_RESOURCE,
+                gfx::memory::Usage::Dynamic,
+                Some(T::get_format().1),
+            )
+            .map_err(|e| error(e.into()))?;
+        let view = factory
+            .view_texture_as_shader_resource::<T>(&texture, (0, 0), gfx::format::Swizzle::new())
+            .map_err(|e| error(e.into()))?;
+
+        Ok((texture, view))
+    }
+
+    fn state(&self, id: PolygonId) -> [u8; 4] {
+        assert!((id.0 as usize) < self.num_polygons, "{:?} is not in the table", id);
+        self.states[id.0 as usize]
+    }
+
+    fn set_channel(&mut self, id: PolygonId, channel: usize, value: u8) {
+        let index = id.0 as usize;
+        assert!(index < self.num_polygons, "{:?} is not in the table", id);
+
+        if self.states[index][channel] != value {
+            self.states[index][channel] = value;
+            self.mark_dirty(index);
+        }
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(index)..dirty.end.max(index + 1),
+            None => index..index + 1,
+        });
+    }
+}
+
+// A texture of `PolygonStates`, and the view the shaders read it through.
+type StateTexture<R, T> = (
+    gfx::handle::Texture<R, <T as gfx::format::Formatted>::Surface>,
+    gfx::handle::ShaderResourceView<R, <T as gfx::format::Formatted>::View>,
+);
+
+/// The width and height of a texture with one texel for each of `num_polygons` polygons, laid out
+/// in rows of at most `MAX_WIDTH` texels. Texel `id % width` of row `id / width` is polygon `id`'s.
+///
+/// # Panics
+///
+/// Panics if there are too many polygons to fit in a texture, i.e. more than 64 million.
+pub(crate) fn texture_size(num_polygons: usize) -> (usize, usize) {
+    let width = num_polygons.clamp(1, MAX_WIDTH);
+    // An empty texture would be invalid, so there is always at least one row.
+    let height = (num_polygons.max(1) - 1) / width + 1;
+    assert!(height <= u16::max_value() as usize);
+    (width, height)
+}
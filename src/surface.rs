@@ -0,0 +1,210 @@
+use std::borrow::Cow;
+use std::f64::consts::PI;
+
+/// The surface that the polygons in a `PolygonBuffer` are draped over.
+///
+/// This determines how a polygon's points are placed in world space, and which direction its
+/// shadow volume is extruded in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Surface {
+    /// A flat terrain, with the given axis as "up".
+    ///
+    /// Polygon points are `(x, y)` coordinates on the ground plane, and shadow volumes are
+    /// extruded along the up-axis. See `UpAxis` for how the ground plane is laid out.
+    Plane(UpAxis),
+    /// The surface of a planet.
+    ///
+    /// Polygon points are `(longitude, latitude)` in degrees, and are placed on the globe in
+    /// earth-centered, earth-fixed (ECEF) coordinates. Shadow volumes are extruded away from the
+    /// globe's center.
+    Globe(Globe),
+}
+
+impl Surface {
+    /// Split long edges of `points` so that they follow the curvature of the surface.
+    pub(crate) fn subdivide<'a>(&self, points: &'a [(f32, f32)]) -> Cow<'a, [(f32, f32)]> {
+        match *self {
+            Surface::Plane(_) => Cow::Borrowed(points),
+            Surface::Globe(ref globe) => Cow::Owned(globe.subdivide(points)),
+        }
+    }
+
+    /// The heights a polygon's shadow volume should span, given the z-range it was added with.
+    ///
+    /// `bounds` are the bounds of the polygon, as passed to `Polygon::new`.
+    pub(crate) fn height_range(&self, z_range: (f32, f32), bounds: [(f32, f32); 2]) -> (f32, f32) {
+        match *self {
+            Surface::Plane(_) => z_range,
+            Surface::Globe(ref globe) => globe.height_range(z_range, bounds),
+        }
+    }
+
+    /// The world-space position of `point` raised to `height`.
+    pub(crate) fn position(&self, point: (f32, f32), height: f32) -> [f32; 3] {
+        match *self {
+            Surface::Plane(up_axis) => up_axis.position(point, height),
+            Surface::Globe(ref globe) => {
+                let position = globe.ellipsoid.to_ecef(point.0, point.1, height);
+                [position[0] as f32, position[1] as f32, position[2] as f32]
+            }
+        }
+    }
+}
+
+/// The world-space axis pointing "up", away from the ground.
+///
+/// Whichever axis is chosen, polygons seen from above keep their orientation: a
+/// counter-clockwise exterior ring remains counter-clockwise. A polygon point `(x, y)` raised to
+/// `height` is placed at:
+///
+/// * `UpAxis::X`: `(height, x, y)`
+/// * `UpAxis::Y`: `(x, height, -y)`
+/// * `UpAxis::Z`: `(x, y, height)`
+///
+/// In other words, with `UpAxis::Y` the polygon's y-axis points along negative Z, which is the
+/// usual "north" of Y-up engines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UpAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl UpAxis {
+    fn position(&self, (x, y): (f32, f32), height: f32) -> [f32; 3] {
+        match *self {
+            UpAxis::X => [height, x, y],
+            UpAxis::Y => [x, height, -y],
+            UpAxis::Z => [x, y, height],
+        }
+    }
+}
+
+/// An ellipsoid of revolution approximating the shape of a planet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Ellipsoid {
+    pub(crate) equatorial_radius: f64,
+    pub(crate) polar_radius: f64,
+}
+
+impl Ellipsoid {
+    /// Construct an ellipsoid from its semi-major and semi-minor axes.
+    pub fn new(equatorial_radius: f64, polar_radius: f64) -> Ellipsoid {
+        Ellipsoid {
+            equatorial_radius: equatorial_radius,
+            polar_radius: polar_radius,
+        }
+    }
+
+    /// Construct a perfect sphere.
+    pub fn sphere(radius: f64) -> Ellipsoid {
+        Ellipsoid::new(radius, radius)
+    }
+
+    /// The WGS84 ellipsoid, in meters.
+    pub fn wgs84() -> Ellipsoid {
+        Ellipsoid::new(6_378_137.0, 6_356_752.314_245)
+    }
+
+    /// Convert a geodetic longitude and latitude, in degrees, and a height above the ellipsoid
+    /// into earth-centered, earth-fixed coordinates.
+    pub fn to_ecef(&self, longitude: f32, latitude: f32, height: f32) -> [f64; 3] {
+        let (longitude, latitude) = (longitude as f64 * PI / 180.0, latitude as f64 * PI / 180.0);
+        let height = height as f64;
+
+        let a2 = self.equatorial_radius * self.equatorial_radius;
+        let b2 = self.polar_radius * self.polar_radius;
+        let e2 = 1.0 - b2 / a2;
+
+        // The prime vertical radius of curvature.
+        let n = self.equatorial_radius / (1.0 - e2 * latitude.sin().powi(2)).sqrt();
+
+        [
+            (n + height) * latitude.cos() * longitude.cos(),
+            (n + height) * latitude.cos() * longitude.sin(),
+            (n * (1.0 - e2) + height) * latitude.sin(),
+        ]
+    }
+}
+
+/// Settings for draping polygons over a globe.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Globe {
+    pub(crate) ellipsoid: Ellipsoid,
+    pub(crate) min_height: f32,
+    pub(crate) max_height: f32,
+    pub(crate) max_edge_length: f32,
+}
+
+impl Globe {
+    /// Describe a globe whose terrain lies between `min_height` and `max_height` above
+    /// `ellipsoid`.
+    ///
+    /// Shadow volumes are extruded between the two heights, so they should be below the lowest
+    /// and above the highest point of your terrain respectively. Polygons added with
+    /// `PolygonBuffer::add_with_heights` map z-values from 0 to 1 onto this range.
+    ///
+    /// Polygon edges longer than `max_edge_length` degrees are split into shorter edges, so that
+    /// long borders follow the curved surface instead of cutting through the terrain.
+    ///
+    /// Vertex positions are stored as `f32`, so on an earth-sized globe they are only accurate to
+    /// within about a meter.
+    pub fn new(
+        ellipsoid: Ellipsoid,
+        min_height: f32,
+        max_height: f32,
+        max_edge_length: f32,
+    ) -> Globe {
+        Globe {
+            ellipsoid: ellipsoid,
+            min_height: min_height,
+            max_height: max_height,
+            max_edge_length: max_edge_length,
+        }
+    }
+
+    fn subdivide(&self, points: &[(f32, f32)]) -> Vec<(f32, f32)> {
+        let mut result = Vec::with_capacity(points.len());
+
+        // Only edges within a ring are subdivided. Nothing joins one ring to the next: each ring
+        // is walled and capped on its own.
+        for ring in ::polygon::rings(points) {
+            for edge in ring.windows(2) {
+                let (from, to) = (edge[0], edge[1]);
+                let length = (to.0 - from.0).abs().max((to.1 - from.1).abs());
+                let steps = (length / self.max_edge_length).ceil().max(1.0) as usize;
+
+                for step in 0..steps {
+                    let t = step as f32 / steps as f32;
+                    result.push((from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t));
+                }
+            }
+
+            result.extend(ring.last());
+        }
+
+        result
+    }
+
+    fn height_range(&self, z_range: (f32, f32), bounds: [(f32, f32); 2]) -> (f32, f32) {
+        let height = |z: f32| self.min_height + (self.max_height - self.min_height) * z;
+
+        // The caps of a shadow volume are made of flat triangles between points on the surface,
+        // and so they sag below the surface in the middle. Raise the top cap by the most any
+        // chord within the polygon's bounds can sag, so it stays above the terrain.
+        let to_radians = |degrees: f32| degrees as f64 * PI / 180.0;
+        let widest_latitude = to_radians(0.0f32.max(bounds[1].0).min(bounds[1].1));
+        let width = to_radians(bounds[0].1 - bounds[0].0) * widest_latitude.cos();
+        let height_span = to_radians(bounds[1].1 - bounds[1].0);
+        let angle = (width * width + height_span * height_span).sqrt().min(PI);
+
+        let radius = self.ellipsoid.equatorial_radius + self.max_height as f64;
+        let sag = radius * (1.0 - (angle / 2.0).cos());
+
+        (height(z_range.0), height(z_range.1) + sag as f32)
+    }
+}
@@ -0,0 +1,429 @@
+use polygon::rings;
+
+/// How the top and bottom caps of the prisms a `PolygonBuffer` builds are split into triangles.
+///
+/// For polygons whose rings are oriented as `Polygon::new` requires, and don't cross each other,
+/// each prism covers exactly the polygon's area in the stencil buffer however its caps are
+/// triangulated, so this only affects how many pixels the polyhedron pass touches, and how long
+/// `PolygonBuffer::add` takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CapTriangulation {
+    /// A fan from the first point of each ring. This is the quickest to build, but for long or
+    /// concave polygons its triangles sweep far outside the polygon, and cancel each other out
+    /// there only after being drawn.
+    Fan,
+    /// Ear clipping, with holes bridged to the exterior ring. Every triangle lies inside the
+    /// polygon, at the cost of time quadratic in the number of points when adding it.
+    ///
+    /// Polygons that can't be clipped, such as self-intersecting ones, or ones whose later rings
+    /// aren't clockwise holes inside the first, fall back to a fan.
+    EarClipping,
+}
+
+impl CapTriangulation {
+    /// Triangulate `points`, a concatenation of rings as passed to `Polygon::new`, returning
+    /// counter-clockwise triangles as indices into `points`.
+    pub(crate) fn triangulate(&self, points: &[(f32, f32)]) -> Vec<u32> {
+        match *self {
+            CapTriangulation::Fan => fan(points),
+            CapTriangulation::EarClipping => ear_clip(points).unwrap_or_else(|| fan(points)),
+        }
+    }
+}
+
+/// A fan from the first point of each ring, whose triangles add up to the polygon because holes
+/// are wound the opposite way to the exterior.
+fn fan(points: &[(f32, f32)]) -> Vec<u32> {
+    let mut triangles = Vec::new();
+    let mut start = 0;
+    for ring in rings(points) {
+        let end = start + ring.len() as u32;
+        let last = if ring.first() == ring.last() { end - 1 } else { end };
+        for index in start + 1..last.saturating_sub(1) {
+            triangles.extend_from_slice(&[start, index, index + 1]);
+        }
+
+        start = end;
+    }
+
+    triangles
+}
+
+/// Triangulate `points` by ear clipping, or return `None` if its holes aren't clockwise and
+/// inside its exterior, or it finds no ear to clip, which happens when the rings intersect.
+fn ear_clip(points: &[(f32, f32)]) -> Option<Vec<u32>> {
+    let positions: Vec<_> = points.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+
+    // Each ring, as indices into `points`, without its closing point if it has one.
+    let mut start = 0;
+    let mut loops = Vec::new();
+    for ring in rings(points) {
+        let end = start + ring.len() as u32;
+        let last = if ring.first() == ring.last() { end - 1 } else { end };
+        if last >= start + 3 {
+            loops.push((start..last).collect::<Vec<_>>());
+        }
+
+        start = end;
+    }
+
+    if loops.is_empty() || signed_area(&positions, &loops[0]) <= 0.0 {
+        return None;
+    }
+
+    let mut outer = loops.remove(0);
+
+    // Rings that aren't holes, such as the separate rectangles of `Polygon::outline`, can't be
+    // bridged to the exterior.
+    let is_hole = |hole: &Vec<u32>| {
+        signed_area(&positions, hole) < 0.0 &&
+            hole.iter()
+                .all(|&index| contains(&positions, &outer, positions[index as usize]))
+    };
+    if !loops.iter().all(is_hole) {
+        return None;
+    }
+
+    // Holes are bridged to the exterior from their rightmost point, rightmost hole first, so
+    // that no bridge crosses a hole yet to be bridged.
+    let rightmost = |hole: &Vec<u32>| {
+        (0..hole.len())
+            .max_by(|&a, &b| {
+                let (a, b) = (positions[hole[a] as usize], positions[hole[b] as usize]);
+                a.partial_cmp(&b).unwrap_or(::std::cmp::Ordering::Equal)
+            })
+            .unwrap()
+    };
+    let mut holes: Vec<_> = loops
+        .into_iter()
+        .map(|hole| {
+            let start = rightmost(&hole);
+            (positions[hole[start] as usize], hole, start)
+        })
+        .collect();
+    holes.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(::std::cmp::Ordering::Equal));
+
+    for (_, hole, start) in holes {
+        let bridge = bridge_vertex(&positions, &outer, positions[hole[start] as usize])?;
+
+        // Walk the exterior to the bridge, around the hole and back.
+        let mut bridged = Vec::with_capacity(outer.len() + hole.len() + 2);
+        bridged.extend_from_slice(&outer[..bridge + 1]);
+        bridged.extend_from_slice(&hole[start..]);
+        bridged.extend_from_slice(&hole[..start + 1]);
+        bridged.extend_from_slice(&outer[bridge..]);
+        outer = bridged;
+    }
+
+    clip_ears(&positions, outer)
+}
+
+/// The position in `outer` of a vertex visible from `point`, a hole's rightmost point, found by
+/// casting a ray from `point` towards positive x (Eberly, "Triangulation by Ear Clipping").
+fn bridge_vertex(positions: &[(f64, f64)], outer: &[u32], point: (f64, f64)) -> Option<usize> {
+    let at = |i: usize| positions[outer[i % outer.len()] as usize];
+
+    // The nearest edge the ray hits, and where.
+    let mut hit: Option<(usize, f64)> = None;
+    for i in 0..outer.len() {
+        let (a, b) = (at(i), at(i + 1));
+        if (a.1 > point.1) == (b.1 > point.1) || a.1 == b.1 {
+            continue;
+        }
+
+        let x = a.0 + (point.1 - a.1) * (b.0 - a.0) / (b.1 - a.1);
+        if x >= point.0 && hit.filter(|&(_, hit_x)| hit_x <= x).is_none() {
+            hit = Some((i, x));
+        }
+    }
+
+    let (edge, x) = hit?;
+    let candidate = if at(edge).0 >= at(edge + 1).0 { edge } else { (edge + 1) % outer.len() };
+    let hit_point = (x, point.1);
+    if hit_point == at(candidate) {
+        return Some(candidate);
+    }
+
+    // The rightmost end of the edge may be hidden behind reflex vertices inside the triangle
+    // between the point, the hit and that end. If so, the one closest in angle to the ray is
+    // visible instead.
+    let triangle = (point, hit_point, at(candidate));
+    let mut best = (candidate, ::std::f64::INFINITY, ::std::f64::INFINITY);
+    for i in 0..outer.len() {
+        let vertex = at(i);
+        let previous = at(i + outer.len() - 1);
+        if i == candidate ||
+            cross(previous, vertex, at(i + 1)) >= 0.0 ||
+            !in_triangle(triangle, vertex)
+        {
+            continue;
+        }
+
+        let (dx, dy) = (vertex.0 - point.0, vertex.1 - point.1);
+        let slope = dy.abs() / dx;
+        let distance = dx * dx + dy * dy;
+        if dx > 0.0 && (slope < best.1 || (slope == best.1 && distance < best.2)) {
+            best = (i, slope, distance);
+        }
+    }
+
+    Some(best.0)
+}
+
+/// Clip ears from `ring`, a simple counter-clockwise polygon given as indices into `positions`.
+fn clip_ears(positions: &[(f64, f64)], mut ring: Vec<u32>) -> Option<Vec<u32>> {
+    let at = |index: u32| positions[index as usize];
+    let mut triangles = Vec::with_capacity(3 * ring.len());
+
+    let mut i = 0;
+    let mut since_clipped = 0;
+    while ring.len() > 3 {
+        if since_clipped > ring.len() {
+            return None;
+        }
+
+        let n = ring.len();
+        let (a, b, c) = (ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]);
+        let turn = cross(at(a), at(b), at(c));
+
+        // Vertices where the ring doubles back on itself, or runs straight on, enclose nothing,
+        // and can be dropped without leaving a gap.
+        let clip = if turn == 0.0 {
+            true
+        } else if turn > 0.0 && is_ear(positions, &ring, (a, b, c)) {
+            triangles.extend_from_slice(&[a, b, c]);
+            true
+        } else {
+            false
+        };
+
+        if clip {
+            ring.remove(i);
+            since_clipped = 0;
+            if i >= ring.len() {
+                i = 0;
+            }
+        } else {
+            i = (i + 1) % n;
+            since_clipped += 1;
+        }
+    }
+
+    if ring.len() == 3 && cross(at(ring[0]), at(ring[1]), at(ring[2])) > 0.0 {
+        triangles.extend_from_slice(&ring);
+    }
+
+    Some(triangles)
+}
+
+/// Whether no other vertex of `ring` lies within the convex corner `(a, b, c)`. Vertices at the
+/// corner's own points, which bridges duplicate, don't count.
+fn is_ear(positions: &[(f64, f64)], ring: &[u32], (a, b, c): (u32, u32, u32)) -> bool {
+    let triangle = (positions[a as usize], positions[b as usize], positions[c as usize]);
+
+    ring.iter().all(|&index| {
+        let point = positions[index as usize];
+        point == triangle.0 || point == triangle.1 || point == triangle.2 ||
+            !in_triangle(triangle, point)
+    })
+}
+
+/// Whether `point` lies within, or on the edges of, `triangle`, whichever way it is wound.
+fn in_triangle((a, b, c): ((f64, f64), (f64, f64), (f64, f64)), point: (f64, f64)) -> bool {
+    let sides = [cross(a, b, point), cross(b, c, point), cross(c, a, point)];
+    sides.iter().all(|&side| side >= 0.0) || sides.iter().all(|&side| side <= 0.0)
+}
+
+/// Whether `point` lies within, or on the boundary of, `ring`, a polygon given as indices into
+/// `positions`.
+fn contains(positions: &[(f64, f64)], ring: &[u32], point: (f64, f64)) -> bool {
+    let at = |i: usize| positions[ring[i % ring.len()] as usize];
+
+    let mut inside = false;
+    for i in 0..ring.len() {
+        let (a, b) = (at(i), at(i + 1));
+        let on_edge = cross(a, b, point) == 0.0 &&
+            point.0 >= a.0.min(b.0) && point.0 <= a.0.max(b.0) &&
+            point.1 >= a.1.min(b.1) && point.1 <= a.1.max(b.1);
+        if on_edge {
+            return true;
+        }
+
+        if (a.1 > point.1) != (b.1 > point.1) &&
+            point.0 < a.0 + (point.1 - a.1) * (b.0 - a.0) / (b.1 - a.1)
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// Twice the signed area of the triangle `(o, a, b)`, which is positive if it turns left.
+fn cross(o: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+fn signed_area(positions: &[(f64, f64)], ring: &[u32]) -> f64 {
+    let at = |i: usize| positions[ring[i % ring.len()] as usize];
+    (0..ring.len())
+        .map(|i| at(i).0 * at(i + 1).1 - at(i + 1).0 * at(i).1)
+        .sum::<f64>() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: (f32, f32), size: f32) -> Vec<(f32, f32)> {
+        let max = (min.0 + size, min.1 + size);
+        vec![min, (max.0, min.1), max, (min.0, max.1), min]
+    }
+
+    fn reversed(ring: Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+        ring.into_iter().rev().collect()
+    }
+
+    /// The areas of `triangles`, which are negative for clockwise ones.
+    fn areas(points: &[(f32, f32)], triangles: &[u32]) -> Vec<f64> {
+        let positions: Vec<_> = points.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+        triangles
+            .chunks(3)
+            .map(|t| {
+                let at = |i: u32| positions[i as usize];
+                cross(at(t[0]), at(t[1]), at(t[2])) / 2.0
+            })
+            .collect()
+    }
+
+    /// Check that ear clipping `points` gives counter-clockwise triangles covering `area`.
+    fn assert_clipped(points: &[(f32, f32)], area: f64) -> Vec<u32> {
+        let triangles = ear_clip(points).expect("the polygon should be clipped");
+        let areas = areas(points, &triangles);
+        assert!(areas.iter().all(|&area| area > 0.0));
+        assert!((areas.iter().sum::<f64>() - area).abs() < 1e-9);
+        triangles
+    }
+
+    #[test]
+    fn convex_polygons_are_clipped_into_two_fewer_triangles_than_points() {
+        let triangles = assert_clipped(&square((0.0, 0.0), 2.0), 4.0);
+        assert_eq!(triangles.len(), 2 * 3);
+
+        let hexagon: Vec<_> = (0..7)
+            .map(|i| {
+                let angle = ::std::f32::consts::PI / 3.0 * (i % 6) as f32;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        assert_eq!(ear_clip(&hexagon).unwrap().len(), 4 * 3);
+    }
+
+    #[test]
+    fn unclosed_rings_keep_their_last_point() {
+        let mut points = square((0.0, 0.0), 2.0);
+        points.pop();
+
+        let triangles = assert_clipped(&points, 4.0);
+        assert!(triangles.contains(&3));
+    }
+
+    #[test]
+    fn concave_polygons_are_clipped_without_leaving_them() {
+        // A U shape, whose fan from the first point would cover the gap between its arms.
+        let points = vec![
+            (0.0, 0.0),
+            (3.0, 0.0),
+            (3.0, 3.0),
+            (2.0, 3.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 3.0),
+            (0.0, 3.0),
+            (0.0, 0.0),
+        ];
+        assert_clipped(&points, 7.0);
+
+        let fan_areas = areas(&points, &fan(&points));
+        assert!(fan_areas.iter().any(|&area| area < 0.0));
+    }
+
+    #[test]
+    fn polygons_with_holes_are_clipped_around_them() {
+        let mut points = square((0.0, 0.0), 10.0);
+        points.extend(reversed(square((2.0, 2.0), 2.0)));
+        assert_clipped(&points, 96.0);
+
+        points.extend(reversed(square((6.0, 5.0), 3.0)));
+        points.extend(reversed(square((2.0, 6.0), 1.0)));
+        assert_clipped(&points, 86.0);
+    }
+
+    #[test]
+    fn degenerate_polygons_are_clipped() {
+        // Points along the square's edges, and a repeated point, enclose nothing.
+        let points = vec![
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 2.0),
+            (0.0, 2.0),
+            (0.0, 1.0),
+            (0.0, 0.0),
+        ];
+        assert_clipped(&points, 4.0);
+
+        let line = vec![(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (0.0, 0.0)];
+        assert_eq!(ear_clip(&line), None);
+        assert_eq!(ear_clip(&[(0.0, 0.0), (1.0, 0.0), (0.0, 0.0)]), None);
+    }
+
+    #[test]
+    fn rings_that_arent_holes_fall_back_to_a_fan() {
+        let exterior = square((0.0, 0.0), 10.0);
+        let cases = vec![
+            // Clockwise exteriors.
+            reversed(exterior.clone()),
+            // Counter-clockwise holes, as in `Polygon::outline`.
+            [exterior.clone(), square((2.0, 2.0), 2.0)].concat(),
+            // Holes outside the exterior.
+            [exterior.clone(), reversed(square((12.0, 2.0), 2.0))].concat(),
+            // Holes crossing the exterior.
+            [exterior.clone(), reversed(square((8.0, 2.0), 4.0))].concat(),
+        ];
+
+        for points in cases {
+            assert_eq!(ear_clip(&points), None);
+            assert_eq!(CapTriangulation::EarClipping.triangulate(&points), fan(&points));
+        }
+    }
+
+    #[test]
+    fn holes_are_bridged_to_visible_vertices() {
+        // The exterior's notch hides its bottom right corner from the hole's rightmost point.
+        let positions = [
+            (0.0, 0.0),
+            (3.0, 0.0),
+            (4.0, 1.5),
+            (4.5, 0.0),
+            (6.0, 0.0),
+            (6.0, 5.0),
+            (0.0, 5.0),
+        ];
+        let outer: Vec<_> = (0..positions.len() as u32).collect();
+
+        assert_eq!(bridge_vertex(&positions, &outer, (1.0, 2.0)), Some(2));
+        assert_eq!(bridge_vertex(&positions, &outer, (1.0, 4.0)), Some(4));
+        assert_eq!(bridge_vertex(&positions, &outer, (7.0, 2.0)), None);
+    }
+
+    #[test]
+    fn clipping_a_ring_with_no_ears_fails() {
+        // Every corner of a clockwise ring is reflex.
+        let square = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        assert_eq!(clip_ears(&square, vec![3, 2, 1, 0]), None);
+        assert_eq!(clip_ears(&square, vec![0, 1, 2, 3]).map(|t| t.len()), Some(6));
+    }
+}
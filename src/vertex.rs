@@ -1,5 +1,9 @@
 #[cfg_attr(rustfmt, rustfmt_skip)]
-gfx_vertex_struct!(Vertex {
-    position: [f32; 3] = "a_position",
-});
-
+gfx_vertex_struct_meta!(
+    #[repr(C)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    vertex_struct_meta Vertex {
+        position: [f32; 3] = "a_position",
+        polygon_id: u32 = "a_polygon_id",
+    }
+);